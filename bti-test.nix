@@ -0,0 +1,38 @@
+{
+  runCommandCC,
+  stdenv,
+  buildPackages,
+  hello,
+  nix-ld,
+}:
+
+# Runs a program through nix-ld with a BTI-marked loader under qemu-user.
+# The loader branches to an instruction without a landing pad, which only
+# faults if nix-ld mapped its code with PROT_BTI and the CPU supports BTI.
+runCommandCC "nix-ld-bti-test-${stdenv.hostPlatform.system}" { } ''
+  install -m755 ${hello}/bin/hello hello
+  patchelf hello --set-interpreter ${nix-ld}/bin/nix-ld
+
+  $CC -nostdlib -static-pie -fPIE -Wl,--no-dynamic-linker \
+    -mbranch-protection=bti -Wl,-z,force-bti \
+    -o bti-loader ${./tests/loader.c}
+
+  run() {
+    status=0
+    QEMU_CPU=$1 NIX_LD=./bti-loader NIX_LD_LOG=debug \
+      ${stdenv.hostPlatform.emulator buildPackages} ./hello > out 2>&1 || status=$?
+    cat out
+    echo "Exit status on $1: $status"
+    grep -q "Branching without a landing pad" out
+  }
+
+  # Killed by SIGILL from the BTI exception
+  run max
+  [ $status = 132 ]
+
+  # Without BTI, nothing is enforced
+  run cortex-a72
+  [ $status = 0 ]
+
+  touch $out
+''
@@ -0,0 +1,50 @@
+{ lib
+, mkShell
+, rustc
+, cargo
+, cargo-watch
+, cargo-bloat
+, cargo-nextest
+, just
+, stdenv
+, qemu
+}:
+
+let
+  rustTarget = stdenv.hostPlatform.rust.rustcTarget;
+  targetSuffix = builtins.replaceStrings [ "-" ] [ "_" ] rustTarget;
+  cargoTarget = lib.toUpper targetSuffix;
+  cc = "${stdenv.cc}/bin/${stdenv.cc.targetPrefix}cc";
+in
+mkShell {
+  nativeBuildInputs = [
+    rustc
+    cargo
+    cargo-watch
+    cargo-bloat
+    cargo-nextest
+    just
+    qemu
+  ];
+
+  hardeningDisable = [ "stackprotector" ];
+
+  RUSTC_BOOTSTRAP = "1";
+
+  # Cross compilation environment
+  "CARGO_TARGET_${cargoTarget}_LINKER" = cc;
+  "CARGO_TARGET_${cargoTarget}_RUNNER" = "qemu-${stdenv.hostPlatform.qemuArch} -L /";
+  "CC_${targetSuffix}" = cc;
+  NIX_LD_TEST_TARGET = rustTarget;
+  NIX_LD = stdenv.cc.bintools.dynamicLinker;
+  TARGET_CC = cc;
+
+  shellHook = ''
+    echo "${stdenv.hostPlatform.system} cross-compilation environment"
+    echo "Target: ${rustTarget}"
+    echo "Cross compiler: ${cc}"
+    echo "Usage:"
+    echo "  cargo build --target ${rustTarget}"
+    echo "  cargo test --target ${rustTarget}"
+  '';
+}
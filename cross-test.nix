@@ -0,0 +1,23 @@
+{
+  runCommand,
+  stdenv,
+  buildPackages,
+  hello,
+  nix-ld,
+}:
+
+# Runs a program through nix-ld under qemu-user. qemu maps nix-ld as
+# the interpreter of the program, so this covers the whole handoff to
+# ld.so, including the entry trampoline.
+runCommand "nix-ld-cross-test-${stdenv.hostPlatform.system}" { } ''
+  install -m755 ${hello}/bin/hello hello
+  patchelf hello --set-interpreter ${nix-ld}/bin/nix-ld
+
+  NIX_LD=${stdenv.cc.bintools.dynamicLinker} NIX_LD_LOG=info \
+    ${stdenv.hostPlatform.emulator buildPackages} ./hello > out 2>&1 || (cat out; exit 1)
+  cat out
+
+  grep -q "Hello, world!" out
+  grep -q "Using entry trampoline" out
+  touch $out
+''
@@ -29,12 +29,17 @@
         { pkgs, system, ... }:
         {
           nix-ld = pkgs.callPackage ./package.nix { };
-          nolibc = pkgs.callPackage ./vendor/nolibc.nix { };
           default = self.packages.${system}.nix-ld;
 
-          # Cross-compiled package for riscv64 (only available on x86_64-linux)
+          # Cross-compiled packages (only available on x86_64-linux)
         } // lib.optionalAttrs (system == "x86_64-linux") {
+          # Tested natively, since x86_64 can run i686 programs
+          nix-ld-i686 = pkgs.pkgsi686Linux.callPackage ./package.nix { };
           nix-ld-riscv64 = pkgs.pkgsCross.riscv64.callPackage ./package.nix { };
+          nix-ld-armv7l = pkgs.pkgsCross.armv7l-hf-multiplatform.callPackage ./package.nix { };
+          nix-ld-s390x = pkgs.pkgsCross.s390x.callPackage ./package.nix { };
+          nix-ld-loongarch64 = pkgs.pkgsCross.loongarch64-linux.callPackage ./package.nix { };
+          nix-ld-ppc64le = pkgs.pkgsCross.powernv.callPackage ./package.nix { };
         }
       );
 
@@ -44,9 +49,23 @@
           nixosTests = pkgs.callPackage ./nixos-tests { };
           packages = lib.mapAttrs' (n: lib.nameValuePair "package-${n}") self.packages.${system};
           devShells = lib.mapAttrs' (n: lib.nameValuePair "devShell-${n}") self.devShells.${system};
+          crossTest =
+            crossPkgs:
+            crossPkgs.callPackage ./cross-test.nix {
+              nix-ld = crossPkgs.callPackage ./package.nix { };
+            };
         in
         packages
         // devShells
+        // lib.optionalAttrs (system == "x86_64-linux") {
+          cross-test-armv7l = crossTest pkgs.pkgsCross.armv7l-hf-multiplatform;
+          cross-test-s390x = crossTest pkgs.pkgsCross.s390x;
+          cross-test-loongarch64 = crossTest pkgs.pkgsCross.loongarch64-linux;
+          cross-test-ppc64le = crossTest pkgs.pkgsCross.powernv;
+          bti-test-aarch64 = pkgs.pkgsCross.aarch64-multiplatform.callPackage ./bti-test.nix {
+            nix-ld = pkgs.pkgsCross.aarch64-multiplatform.callPackage ./package.nix { };
+          };
+        }
         // lib.optionalAttrs (system != "i686-linux") {
           # test driver is broken on i686-linux
           inherit (nixosTests) basic;
@@ -55,6 +74,13 @@
           clippy = self.packages.${system}.nix-ld.override {
             enableClippy = true;
           };
+          no-entry-trampoline = self.packages.${system}.nix-ld.override {
+            buildNoDefaultFeatures = true;
+          };
+          # Also runs the test asserting that DT_RELR is used
+          pack-relative-relocs = self.packages.${system}.nix-ld.override {
+            buildFeatures = [ "pack_relative_relocs" ];
+          };
         }
       );
 
@@ -84,16 +110,24 @@
             shellHook = ''
               echo "nix-ld development environment"
             '' + lib.optionalString (system == "x86_64-linux") ''
-              echo "Available cross-compilation shell:"
+              echo "Available cross-compilation shells:"
               echo "  nix develop .#cross-riscv64  - Cross compile to riscv64"
+              echo "  nix develop .#cross-armv7l   - Cross compile to armv7l"
+              echo "  nix develop .#cross-s390x    - Cross compile to s390x"
+              echo "  nix develop .#cross-loongarch64 - Cross compile to loongarch64"
+              echo "  nix develop .#cross-ppc64le  - Cross compile to ppc64le"
             '';
           };
 
           # Default cross shell for current system
           default = self.devShells.${system}.nix-ld;
         } // lib.optionalAttrs (system == "x86_64-linux") {
-          # Cross compilation shell for riscv64 (only available on x86_64-linux)
-          cross-riscv64 = pkgs.pkgsCross.riscv64.callPackage ./riscv64-shell.nix { };
+          # Cross compilation shells (only available on x86_64-linux)
+          cross-riscv64 = pkgs.pkgsCross.riscv64.callPackage ./cross-shell.nix { };
+          cross-armv7l = pkgs.pkgsCross.armv7l-hf-multiplatform.callPackage ./cross-shell.nix { };
+          cross-s390x = pkgs.pkgsCross.s390x.callPackage ./cross-shell.nix { };
+          cross-loongarch64 = pkgs.pkgsCross.loongarch64-linux.callPackage ./cross-shell.nix { };
+          cross-ppc64le = pkgs.pkgsCross.powernv.callPackage ./cross-shell.nix { };
         }
       );
     }
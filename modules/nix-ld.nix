@@ -58,6 +58,12 @@ in
       default = baseLibraries;
       defaultText = lib.literalExpression "baseLibraries derived from systemd and nix dependencies.";
     };
+    trustedLoaderPrefixes = lib.mkOption {
+      type = lib.types.listOf lib.types.str;
+      description = "If non-empty, only loaders under these prefixes that are owned by root and not writable by group or others can be used.";
+      default = [ ];
+      example = [ "/nix/store" ];
+    };
   };
 
   config = lib.mkIf config.programs.nix-ld.dev.enable {
@@ -78,6 +84,13 @@ in
 
     environment.pathsToLink = [ "/share/nix-ld" ];
 
+    # Also applies to programs launched with a scrubbed environment
+    environment.etc."nix-ld/config".text = ''
+      NIX_LD = /run/current-system/sw/share/nix-ld/lib/ld.so
+      NIX_LD_LIBRARY_PATH = /run/current-system/sw/share/nix-ld/lib
+    ''
+    + lib.concatMapStrings (prefix: "trusted-loader-prefix = ${prefix}\n") cfg.trustedLoaderPrefixes;
+
     environment.variables = {
       NIX_LD = "/run/current-system/sw/share/nix-ld/lib/ld.so";
       NIX_LD_LIBRARY_PATH = "/run/current-system/sw/share/nix-ld/lib";
@@ -4,6 +4,8 @@
   pkgs,
   rustPlatform,
   enableClippy ? false,
+  buildFeatures ? [ ],
+  buildNoDefaultFeatures ? false,
 }:
 
 let
@@ -24,6 +26,8 @@ let
 
     cargoLock.lockFile = ./Cargo.lock;
 
+    inherit buildFeatures buildNoDefaultFeatures;
+
     src = lib.fileset.toSource {
       root = ./.;
       fileset = lib.fileset.unions [
@@ -31,7 +35,6 @@ let
         ./Cargo.toml
         ./Cargo.lock
         ./build.rs
-        ./vendor
         ./tests
       ];
     };
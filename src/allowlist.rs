@@ -0,0 +1,108 @@
+//! Execution allowlist.
+//!
+//! When `allowlist` is set in the system-wide config file, nix-ld only
+//! runs programs listed in that file. Each line is either a glob matched
+//! against the real path of the program, or the SHA-256 of its contents:
+//!
+//! ```text
+//! # /etc/nix-ld/allowlist
+//! path /opt/vendor/bin/*
+//! sha256 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08
+//! ```
+//!
+//! The real path comes from `/proc/self/exe` rather than `AT_EXECFN`,
+//! which may be a symlink chosen by the caller.
+//!
+//! Executing nix-ld directly is denied while the allowlist is in effect,
+//! since the program is then just an argument to ld.so.
+
+use core::ffi::{CStr, c_int};
+
+use crate::config::{self, AsStr};
+use crate::sha256::{DIGEST_LEN, Sha256};
+use crate::sys::{self, Error as IoError, File, Read};
+use crate::target::{self, SELF_EXE};
+
+/// The exit status when a program is denied.
+pub const DENIED_STATUS: c_int = 126;
+
+/// Exits unless the program being loaded is in the allowlist.
+pub fn enforce(allowlist: &CStr) {
+    let buf = match config::read_to_leaked(allowlist) {
+        Ok(buf) => config::terminate_lines(buf),
+        Err(e) => deny(format_args!(
+            "Couldn't read the allowlist {allowlist:?}: {e}"
+        )),
+    };
+
+    let real_path = match target::real_path() {
+        Ok(path) => path,
+        Err(e) => deny(format_args!("Couldn't resolve the program: {e}")),
+    };
+    let real_path = real_path.as_slice();
+
+    // Hashing is expensive, so only do it once and when needed
+    let mut digest = None;
+
+    for line in buf.split(|b| *b == 0) {
+        let line = line.trim_ascii_start();
+        if line.is_empty() || line.starts_with(b"#") {
+            continue;
+        }
+
+        let (kind, value) = match line.iter().position(u8::is_ascii_whitespace) {
+            Some(space) => (&line[..space], line[space..].trim_ascii_start()),
+            None => (line, &[][..]),
+        };
+
+        let allowed = match kind {
+            b"path" => config::glob_match(value, real_path),
+            b"sha256" => {
+                let digest = digest.get_or_insert_with(|| match hash_self() {
+                    Ok(digest) => digest,
+                    Err(e) => deny(format_args!("Couldn't hash {:?}: {e}", AsStr(real_path))),
+                });
+                config::hex_eq(value, digest)
+            }
+            _ => {
+                log::warn!("Ignoring malformed allowlist line {:?}", AsStr(line));
+                false
+            }
+        };
+
+        if allowed {
+            log::info!("{:?} is allowed by {:?}", AsStr(real_path), AsStr(line));
+            return;
+        }
+    }
+
+    deny(format_args!(
+        "{:?} is not in the allowlist",
+        AsStr(real_path)
+    ));
+}
+
+/// Exits because nix-ld was executed directly.
+pub fn deny_direct_execution() -> ! {
+    deny(format_args!("nix-ld was executed directly"))
+}
+
+/// Computes the SHA-256 of the program.
+fn hash_self() -> Result<[u8; DIGEST_LEN], IoError> {
+    let mut file = File::open_cstr(SELF_EXE)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 16 * 1024];
+
+    loop {
+        match file.read(&mut buf)? {
+            0 => return Ok(hasher.finish()),
+            n => hasher.update(&buf[..n]),
+        }
+    }
+}
+
+#[cold]
+fn deny(reason: core::fmt::Arguments) -> ! {
+    log::error!("Refusing to run the program: {reason}");
+    sys::exit(DENIED_STATUS)
+}
@@ -4,11 +4,27 @@ use core::ffi::c_void;
 use core::mem;
 use core::ptr;
 
-use crate::args::EnvEdit;
+use crate::args::{EnvRevert, MAX_ENV_REVERTS};
 use crate::const_concat::concat;
 
 #[cfg(not(target_os = "linux"))]
-compiler_error!("Only Linux is supported");
+compile_error!("Only Linux is supported");
+
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "x86",
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "riscv64",
+    target_arch = "s390x",
+    target_arch = "loongarch64",
+    all(target_arch = "powerpc64", target_endian = "little")
+)))]
+compile_error!("Unsupported architecture, see src/arch.rs for what a port needs");
+
+// _start and the entry trampoline are written for the ARM state
+#[cfg(all(target_arch = "arm", target_feature = "thumb-mode"))]
+compile_error!("Thumb mode is not supported, build for an ARM-state target");
 
 #[cfg(target_pointer_width = "32")]
 pub use goblin::elf32 as elf_types;
@@ -24,10 +40,18 @@ pub const EM_SELF: u16 = {
     const VALUE: u16 = EM_X86_64;
     #[cfg(target_arch = "x86")]
     const VALUE: u16 = EM_386;
+    #[cfg(target_arch = "arm")]
+    const VALUE: u16 = EM_ARM;
     #[cfg(target_arch = "aarch64")]
     const VALUE: u16 = EM_AARCH64;
     #[cfg(target_arch = "riscv64")]
     const VALUE: u16 = EM_RISCV;
+    #[cfg(target_arch = "s390x")]
+    const VALUE: u16 = EM_S390;
+    #[cfg(target_arch = "loongarch64")]
+    const VALUE: u16 = EM_LOONGARCH;
+    #[cfg(target_arch = "powerpc64")]
+    const VALUE: u16 = EM_PPC64;
     VALUE
 };
 
@@ -37,13 +61,60 @@ pub const R_RELATIVE: u32 = {
     const VALUE: u32 = R_X86_64_RELATIVE;
     #[cfg(target_arch = "x86")]
     const VALUE: u32 = R_386_RELATIVE;
+    #[cfg(target_arch = "arm")]
+    const VALUE: u32 = R_ARM_RELATIVE;
     #[cfg(target_arch = "aarch64")]
     const VALUE: u32 = R_AARCH64_RELATIVE;
     #[cfg(target_arch = "riscv64")]
     const VALUE: u32 = R_RISCV_RELATIVE;
+    // Not in goblin
+    #[cfg(target_arch = "s390x")]
+    const VALUE: u32 = 12; // R_390_RELATIVE
+    #[cfg(target_arch = "loongarch64")]
+    const VALUE: u32 = R_LARCH_RELATIVE;
+    #[cfg(target_arch = "powerpc64")]
+    const VALUE: u32 = R_PPC64_RELATIVE;
+    VALUE
+};
+
+/// The `GNU_PROPERTY_*_FEATURE_1_AND` property of this architecture.
+pub const GNU_PROPERTY_FEATURE_1_AND: Option<u32> = {
+    #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+    const VALUE: Option<u32> = Some(0xc000_0002);
+    #[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+    const VALUE: Option<u32> = Some(0xc000_0000);
+    #[cfg(any(
+        target_arch = "arm",
+        target_arch = "s390x",
+        target_arch = "loongarch64",
+        target_arch = "powerpc64"
+    ))]
+    const VALUE: Option<u32> = None;
     VALUE
 };
 
+/// Returns the extra protection flags for executable mappings of
+/// an ELF with the given `GNU_PROPERTY_*_FEATURE_1_AND` bits.
+///
+/// Only BTI on aarch64 is enforced through the mapping, as the kernel
+/// does for interpreters. The x86 CET bits have no protection flag and
+/// the kernel doesn't act on them for interpreters either: glibc's
+/// ld.so enables IBT and shadow stacks itself through `arch_prctl`
+/// after checking the program and its libraries, so they are only
+/// logged. Other architectures have no such properties.
+pub fn feature_1_prot(features: u32) -> u32 {
+    #[cfg(target_arch = "aarch64")]
+    {
+        const GNU_PROPERTY_AARCH64_FEATURE_1_BTI: u32 = 1 << 0;
+        if features & GNU_PROPERTY_AARCH64_FEATURE_1_BTI != 0 {
+            return linux_raw_sys::general::PROT_BTI;
+        }
+    }
+
+    let _ = features;
+    0
+}
+
 pub const NIX_SYSTEM: &str = match option_env!("NIX_SYSTEM") {
     Some(system) => system,
     None => {
@@ -51,10 +122,18 @@ pub const NIX_SYSTEM: &str = match option_env!("NIX_SYSTEM") {
         const VALUE: &str = "x86_64_linux";
         #[cfg(target_arch = "x86")]
         const VALUE: &str = "i686_linux";
+        #[cfg(target_arch = "arm")]
+        const VALUE: &str = "armv7l_linux";
         #[cfg(target_arch = "aarch64")]
         const VALUE: &str = "aarch64_linux";
         #[cfg(target_arch = "riscv64")]
         const VALUE: &str = "riscv64_linux";
+        #[cfg(target_arch = "s390x")]
+        const VALUE: &str = "s390x_linux";
+        #[cfg(target_arch = "loongarch64")]
+        const VALUE: &str = "loongarch64_linux";
+        #[cfg(target_arch = "powerpc64")]
+        const VALUE: &str = "powerpc64le_linux";
         VALUE
     }
 };
@@ -64,6 +143,280 @@ pub const NIX_LD_LIBRARY_PATH_SYSTEM_ENV: &str = concat!("NIX_LD_LIBRARY_PATH_",
 pub const NIX_LD_SYSTEM_ENV_BYTES: &[u8] = NIX_LD_SYSTEM_ENV.as_bytes();
 pub const NIX_LD_LIBRARY_PATH_SYSTEM_ENV_BYTES: &[u8] = NIX_LD_LIBRARY_PATH_SYSTEM_ENV.as_bytes();
 
+/// The process entry point.
+///
+/// Passes the initial stack pointer, where the kernel put argc, argv,
+/// envp and auxv, to `entry` on an aligned stack.
+#[unsafe(naked)]
+#[unsafe(no_mangle)]
+unsafe extern "C" fn _start() -> ! {
+    #[cfg(target_arch = "x86_64")]
+    core::arch::naked_asm!(
+        "xor ebp, ebp",
+        "mov rdi, rsp",
+        "and rsp, -16",
+        "call {entry}",
+        "ud2",
+        entry = sym entry,
+    );
+    #[cfg(target_arch = "x86")]
+    core::arch::naked_asm!(
+        "xor ebp, ebp",
+        "mov eax, esp",
+        "and esp, -16",
+        "sub esp, 12",
+        "push eax",
+        "call {entry}",
+        "ud2",
+        entry = sym entry,
+    );
+    #[cfg(target_arch = "arm")]
+    core::arch::naked_asm!(
+        "mov fp, #0",
+        "mov lr, #0",
+        "mov r0, sp",
+        "bic r1, r0, #15",
+        "mov sp, r1",
+        "bl {entry}",
+        "udf #0",
+        entry = sym entry,
+    );
+    #[cfg(target_arch = "aarch64")]
+    core::arch::naked_asm!(
+        "mov x29, #0",
+        "mov x30, #0",
+        "mov x0, sp",
+        "and x1, x0, #-16",
+        "mov sp, x1",
+        "bl {entry}",
+        "brk #0",
+        entry = sym entry,
+    );
+    #[cfg(target_arch = "riscv64")]
+    core::arch::naked_asm!(
+        ".option push",
+        ".option norelax",
+        "lla gp, __global_pointer$",
+        ".option pop",
+        "li fp, 0",
+        "li ra, 0",
+        "mv a0, sp",
+        "andi sp, sp, -16",
+        "call {entry}",
+        "unimp",
+        entry = sym entry,
+    );
+    // The callee expects a register save area and a zero back chain
+    #[cfg(target_arch = "s390x")]
+    core::arch::naked_asm!(
+        "lgr %r2, %r15",
+        "aghi %r15, -160",
+        "xc 0(8,%r15), 0(%r15)",
+        "brasl %r14, {entry}",
+        "j .",
+        entry = sym entry,
+    );
+    #[cfg(target_arch = "loongarch64")]
+    core::arch::naked_asm!(
+        "move $fp, $zero",
+        "move $ra, $zero",
+        "move $a0, $sp",
+        "bstrins.d $sp, $zero, 3, 0",
+        "bl {entry}",
+        "break 0",
+        entry = sym entry,
+    );
+    // The kernel puts the entry point in r12, from which we compute
+    // the TOC pointer like an ELFv2 global entry point does
+    #[cfg(target_arch = "powerpc64")]
+    core::arch::naked_asm!(
+        "0:",
+        "addis 2, 12, (.TOC. - 0b)@ha",
+        "addi 2, 2, (.TOC. - 0b)@l",
+        "mr 3, 1",
+        "clrrdi 1, 1, 4",
+        "li 0, 0",
+        "stdu 0, -32(1)",
+        "bl {entry}",
+        "nop",
+        "trap",
+        entry = sym entry,
+    );
+}
+
+// This runs before we relocate ourselves, so it must not touch any
+// global data
+unsafe extern "C" fn entry(sp: *const usize) -> ! {
+    unsafe {
+        let argc = *sp;
+        let argv = sp.add(1).cast::<*const u8>();
+        let envp = argv.add(argc + 1);
+        crate::main(argc, argv, envp)
+    }
+}
+
+/// Performs a system call.
+///
+/// Returns the raw result, which is `-errno` on failure. Unused
+/// arguments are passed as zero, which the kernel ignores.
+#[inline(always)]
+pub unsafe fn syscall(nr: u32, args: [usize; 6]) -> usize {
+    let ret;
+    let nr = nr as usize;
+
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        core::arch::asm!(
+            "syscall",
+            inlateout("rax") nr => ret,
+            in("rdi") args[0],
+            in("rsi") args[1],
+            in("rdx") args[2],
+            in("r10") args[3],
+            in("r8") args[4],
+            in("r9") args[5],
+            lateout("rcx") _,
+            lateout("r11") _,
+            options(nostack),
+        );
+    }
+
+    // esi and ebp cannot be asm operands, so we load them from
+    // memory along with the syscall number
+    #[cfg(target_arch = "x86")]
+    unsafe {
+        let extra = [nr, args[3], args[5]];
+        core::arch::asm!(
+            "push ebp",
+            "push esi",
+            "mov esi, [eax + 4]",
+            "mov ebp, [eax + 8]",
+            "mov eax, [eax]",
+            "int 0x80",
+            "pop esi",
+            "pop ebp",
+            inlateout("eax") extra.as_ptr() => ret,
+            in("ebx") args[0],
+            in("ecx") args[1],
+            in("edx") args[2],
+            in("edi") args[4],
+        );
+    }
+
+    #[cfg(target_arch = "arm")]
+    unsafe {
+        core::arch::asm!(
+            "svc 0",
+            in("r7") nr,
+            inlateout("r0") args[0] => ret,
+            in("r1") args[1],
+            in("r2") args[2],
+            in("r3") args[3],
+            in("r4") args[4],
+            in("r5") args[5],
+            options(nostack),
+        );
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!(
+            "svc 0",
+            in("x8") nr,
+            inlateout("x0") args[0] => ret,
+            in("x1") args[1],
+            in("x2") args[2],
+            in("x3") args[3],
+            in("x4") args[4],
+            in("x5") args[5],
+            options(nostack),
+        );
+    }
+
+    #[cfg(target_arch = "riscv64")]
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a7") nr,
+            inlateout("a0") args[0] => ret,
+            in("a1") args[1],
+            in("a2") args[2],
+            in("a3") args[3],
+            in("a4") args[4],
+            in("a5") args[5],
+            options(nostack),
+        );
+    }
+
+    #[cfg(target_arch = "s390x")]
+    unsafe {
+        core::arch::asm!(
+            "svc 0",
+            in("r1") nr,
+            inlateout("r2") args[0] => ret,
+            in("r3") args[1],
+            in("r4") args[2],
+            in("r5") args[3],
+            in("r6") args[4],
+            in("r7") args[5],
+            options(nostack),
+        );
+    }
+
+    #[cfg(target_arch = "loongarch64")]
+    unsafe {
+        core::arch::asm!(
+            "syscall 0",
+            in("$a7") nr,
+            inlateout("$a0") args[0] => ret,
+            in("$a1") args[1],
+            in("$a2") args[2],
+            in("$a3") args[3],
+            in("$a4") args[4],
+            in("$a5") args[5],
+            lateout("$t0") _,
+            lateout("$t1") _,
+            lateout("$t2") _,
+            lateout("$t3") _,
+            lateout("$t4") _,
+            lateout("$t5") _,
+            lateout("$t6") _,
+            lateout("$t7") _,
+            lateout("$t8") _,
+            options(nostack),
+        );
+    }
+
+    // On error, the summary overflow bit of cr0 is set and r3 holds
+    // the positive errno
+    #[cfg(target_arch = "powerpc64")]
+    unsafe {
+        core::arch::asm!(
+            "sc",
+            "bns+ 1f",
+            "neg 3, 3",
+            "1:",
+            inlateout("r0") nr => _,
+            inlateout("r3") args[0] => ret,
+            inlateout("r4") args[1] => _,
+            inlateout("r5") args[2] => _,
+            inlateout("r6") args[3] => _,
+            inlateout("r7") args[4] => _,
+            inlateout("r8") args[5] => _,
+            lateout("r9") _,
+            lateout("r10") _,
+            lateout("r11") _,
+            lateout("r12") _,
+            lateout("cr0") _,
+            lateout("ctr") _,
+            lateout("xer") _,
+            options(nostack),
+        );
+    }
+
+    ret
+}
+
 // Note: We separate main_relocate_stack and elf_jmp to make stack alignment
 // easier. For elf_jmp, we expect the loader to take care of aligning the
 // stack pointer in _start.
@@ -74,10 +427,21 @@ macro_rules! main_relocate_stack {
         core::arch::asm!("mov rsp, {}; call {}", in(reg) $sp, sym $func, options(noreturn));
         #[cfg(target_arch = "x86")]
         core::arch::asm!("mov esp, {}; call {}", in(reg) $sp, sym $func, options(noreturn));
+        #[cfg(target_arch = "arm")]
+        core::arch::asm!("mov sp, {}; bl {}", in(reg) $sp, sym $func, options(noreturn));
         #[cfg(target_arch = "aarch64")]
         core::arch::asm!("mov sp, {}; bl {}", in(reg) $sp, sym $func, options(noreturn));
         #[cfg(target_arch = "riscv64")]
         core::arch::asm!("mv sp, {}; call {}", in(reg) $sp, sym $func, options(noreturn));
+        // The callee expects a register save area and a zero back chain
+        #[cfg(target_arch = "s390x")]
+        core::arch::asm!("lgr %r15, {}; aghi %r15, -160; xc 0(8,%r15), 0(%r15); brasl %r14, {}", in(reg) $sp, sym $func, options(noreturn));
+        #[cfg(target_arch = "loongarch64")]
+        core::arch::asm!("move $sp, {}; bl {}", in(reg) $sp, sym $func, options(noreturn));
+        // The callee saves the link register in our frame, so we need
+        // a minimal one with a null back chain
+        #[cfg(target_arch = "powerpc64")]
+        core::arch::asm!("mr 1, {}; li 0, 0; stdu 0, -32(1); bl {}", in(reg) $sp, sym $func, options(noreturn));
     };
 }
 pub(crate) use main_relocate_stack;
@@ -88,116 +452,313 @@ macro_rules! elf_jmp {
         core::arch::asm!("mov rsp, {}; jmp {}", in(reg) $sp, in(reg) $target, options(noreturn));
         #[cfg(target_arch = "x86")]
         core::arch::asm!("mov esp, {}; jmp {}", in(reg) $sp, in(reg) $target, options(noreturn));
+        #[cfg(target_arch = "arm")]
+        core::arch::asm!("mov sp, {}; bx {}", in(reg) $sp, in(reg) $target, options(noreturn));
+        // br x16 is allowed to land on `bti c`, even from guarded pages
         #[cfg(target_arch = "aarch64")]
-        core::arch::asm!("mov sp, {}; br {}", in(reg) $sp, in(reg) $target, options(noreturn));
+        core::arch::asm!("mov sp, {}; br x16", in(reg) $sp, in("x16") $target, options(noreturn));
         #[cfg(target_arch = "riscv64")]
         core::arch::asm!("mv sp, {}; jr {}", in(reg) $sp, in(reg) $target, options(noreturn));
+        // br %r0 is a no-op, so don't let the target end up there
+        #[cfg(target_arch = "s390x")]
+        core::arch::asm!("lgr %r15, {}; br %r1", in(reg) $sp, in("r1") $target, options(noreturn));
+        #[cfg(target_arch = "loongarch64")]
+        core::arch::asm!("move $sp, {}; jr {}", in(reg) $sp, in(reg) $target, options(noreturn));
+        // ELFv2 global entry points compute the TOC pointer from r12
+        #[cfg(target_arch = "powerpc64")]
+        core::arch::asm!("mr 1, {}; mtctr 12; bctr", in(reg) $sp, in("r12") $target, options(noreturn));
     };
 }
 pub(crate) use elf_jmp;
 
 /// Context for the entry point trampoline.
 ///
-/// The goal is to revert our LD_LIBRARY_PATH changes once
-/// ld.so has done its job.
+/// The goal is to revert our environment changes (like LD_LIBRARY_PATH)
+/// once ld.so has done its job.
 #[repr(C, align(4096))]
 #[derive(Debug)]
 pub struct TrampolineContext {
     elf_entry: *const c_void,
-    env_entry: *const *const u8,
-    env_string: *const u8,
+
+    /// Spill slot for trampolines that are short on registers.
+    scratch: usize,
+
+    /// The environment slots to restore, terminated by a null entry.
+    env_reverts: [EnvRevert; MAX_ENV_REVERTS + 1],
 }
 
 impl TrampolineContext {
     #[allow(unused)]
-    const ENV_ENTRY_OFFSET: usize = mem::size_of::<*const u8>();
+    const SCRATCH_OFFSET: usize = mem::offset_of!(Self, scratch);
+
+    #[allow(unused)]
+    const ENV_REVERTS_OFFSET: usize = mem::offset_of!(Self, env_reverts);
 
     #[allow(unused)]
-    const ENV_STRING_OFFSET: usize = mem::size_of::<*const u8>() * 2;
+    const ENV_REVERT_SIZE: usize = mem::size_of::<EnvRevert>();
+
+    #[allow(unused)]
+    const ENV_STRING_OFFSET: usize = mem::offset_of!(EnvRevert, string);
 
     pub fn set_elf_entry(&mut self, entry: *const c_void) {
         self.elf_entry = entry;
     }
 
-    pub fn revert_env(&mut self, edit: &EnvEdit) {
-        self.env_entry = edit.entry;
-        self.env_string = edit.old_string;
-    }
-
-    pub fn revert_env_entry(&mut self, entry: *const *const u8) {
-        self.env_entry = entry;
+    pub fn set_env_reverts(&mut self, reverts: &[EnvRevert]) {
+        self.env_reverts[..reverts.len()].copy_from_slice(reverts);
     }
 }
 
 pub static mut TRAMPOLINE_CONTEXT: TrampolineContext = TrampolineContext {
     elf_entry: ptr::null(),
-    env_entry: ptr::null(),
-    env_string: ptr::null(),
+    scratch: 0,
+    env_reverts: [EnvRevert {
+        entry: ptr::null(),
+        string: ptr::null(),
+    }; MAX_ENV_REVERTS + 1],
 };
 
 #[cfg(not(feature = "entry_trampoline"))]
 pub const ENTRY_TRAMPOLINE: Option<unsafe extern "C" fn() -> !> = None;
 
-#[cfg(target_arch = "x86_64")]
+#[cfg(all(feature = "entry_trampoline", target_arch = "x86_64"))]
 pub const ENTRY_TRAMPOLINE: Option<unsafe extern "C" fn() -> !> = Some(entry_trampoline);
 
-#[cfg(target_arch = "riscv64")]
+#[cfg(all(feature = "entry_trampoline", target_arch = "riscv64"))]
 pub const ENTRY_TRAMPOLINE: Option<unsafe extern "C" fn() -> !> = Some(entry_trampoline);
 
-#[cfg(target_arch = "x86_64")]
+#[cfg(all(feature = "entry_trampoline", target_arch = "x86_64"))]
 #[unsafe(naked)]
 unsafe extern "C" fn entry_trampoline() -> ! {
     core::arch::naked_asm!(
-        "lea r10, [rip + {context}]",
-        "mov r11, [r10 + {size} * 1]", // .env_entry
-        "test r11, r11",
-        "jz 2f",
-        "mov r10, [r10 + {size} * 2]", // .env_string
-        "mov [r11], r10",
+        "lea r10, [rip + {context} + {env_reverts_off}]",
         "2:",
+        "mov r11, [r10]", // .entry
+        "test r11, r11",
+        "jz 3f",
+        "push qword ptr [r10 + {env_string_off}]", // .string
+        "pop qword ptr [r11]",
+        "add r10, {env_revert_size}",
+        "jmp 2b",
+        "3:",
         "jmp [rip + {context}]",
         context = sym TRAMPOLINE_CONTEXT,
-        size = const core::mem::size_of::<*const u8>(),
+        env_reverts_off = const TrampolineContext::ENV_REVERTS_OFFSET,
+        env_revert_size = const TrampolineContext::ENV_REVERT_SIZE,
+        env_string_off = const TrampolineContext::ENV_STRING_OFFSET,
+    )
+}
+
+#[cfg(all(feature = "entry_trampoline", target_arch = "x86"))]
+pub const ENTRY_TRAMPOLINE: Option<unsafe extern "C" fn() -> !> = Some(entry_trampoline);
+
+// edx carries the rtld_fini pointer to _start, so we only touch
+// eax, ecx and a saved ebx. There is no PC-relative addressing mode,
+// so we get the address with call/pop. The expression with two
+// symbols is rejected in Intel syntax.
+#[cfg(all(feature = "entry_trampoline", target_arch = "x86"))]
+#[unsafe(naked)]
+unsafe extern "C" fn entry_trampoline() -> ! {
+    core::arch::naked_asm!(
+        "call 3f",
+        "3:",
+        "pop %ecx",
+        "lea {context} - 3b(%ecx), %ecx",
+        "push %ebx",
+        "lea {env_reverts_off}(%ecx), %eax",
+        "2:",
+        "mov (%eax), %ebx", // .entry
+        "test %ebx, %ebx",
+        "jz 4f",
+        "pushl {env_string_off}(%eax)", // .string
+        "popl (%ebx)",
+        "add ${env_revert_size}, %eax",
+        "jmp 2b",
+        "4:",
+        "pop %ebx",
+        "jmp *(%ecx)",
+        context = sym TRAMPOLINE_CONTEXT,
+        env_reverts_off = const TrampolineContext::ENV_REVERTS_OFFSET,
+        env_revert_size = const TrampolineContext::ENV_REVERT_SIZE,
+        env_string_off = const TrampolineContext::ENV_STRING_OFFSET,
+        options(att_syntax),
     )
 }
 
-#[cfg(target_arch = "aarch64")]
+#[cfg(all(feature = "entry_trampoline", target_arch = "arm"))]
 pub const ENTRY_TRAMPOLINE: Option<unsafe extern "C" fn() -> !> = Some(entry_trampoline);
 
-#[cfg(target_arch = "aarch64")]
+// r0 carries the rtld_fini pointer to _start. The offset to the
+// context is stored after the code, relative to the pc, which is
+// 8 bytes ahead in ARM state. bx lets the entry point be Thumb.
+#[cfg(all(feature = "entry_trampoline", target_arch = "arm"))]
+#[unsafe(naked)]
+unsafe extern "C" fn entry_trampoline() -> ! {
+    core::arch::naked_asm!(
+        "ldr r12, 3f",
+        "1:",
+        "add r12, pc, r12",
+        "add r1, r12, #{env_reverts_off}",
+        "2:",
+        "ldr r2, [r1]", // .entry
+        "cmp r2, #0",
+        "beq 4f",
+        "ldr r3, [r1, #{env_string_off}]", // .string
+        "str r3, [r2]",
+        "add r1, r1, #{env_revert_size}",
+        "b 2b",
+        "4:",
+        "ldr r12, [r12]",
+        "bx r12",
+        "3:",
+        ".word {context} - (1b + 8)",
+        context = sym TRAMPOLINE_CONTEXT,
+        env_reverts_off = const TrampolineContext::ENV_REVERTS_OFFSET,
+        env_revert_size = const TrampolineContext::ENV_REVERT_SIZE,
+        env_string_off = const TrampolineContext::ENV_STRING_OFFSET,
+    )
+}
+
+#[cfg(all(feature = "entry_trampoline", target_arch = "aarch64"))]
+pub const ENTRY_TRAMPOLINE: Option<unsafe extern "C" fn() -> !> = Some(entry_trampoline);
+
+#[cfg(all(feature = "entry_trampoline", target_arch = "aarch64"))]
 #[unsafe(naked)]
 unsafe extern "C" fn entry_trampoline() -> ! {
     core::arch::naked_asm!(
         "adrp x8, {context}",
-        "ldr x9, [x8, {env_entry_off}]", // .env_entry
-        "cbz x9, 2f",
-        "ldr x10, [x8, {env_string_off}]", // .env_string
-        "str x10, [x9]",
+        "add x9, x8, {env_reverts_off}",
         "2:",
+        "ldp x10, x11, [x9], {env_revert_size}", // .entry, .string
+        "cbz x10, 3f",
+        "str x11, [x10]",
+        "b 2b",
+        "3:",
         "ldr x8, [x8]",
         "br x8",
         context = sym TRAMPOLINE_CONTEXT,
-        env_entry_off = const TrampolineContext::ENV_ENTRY_OFFSET,
-        env_string_off = const TrampolineContext::ENV_STRING_OFFSET,
+        env_reverts_off = const TrampolineContext::ENV_REVERTS_OFFSET,
+        env_revert_size = const TrampolineContext::ENV_REVERT_SIZE,
     )
 }
 
-#[cfg(target_arch = "riscv64")]
+#[cfg(all(feature = "entry_trampoline", target_arch = "riscv64"))]
 #[unsafe(naked)]
 unsafe extern "C" fn entry_trampoline() -> ! {
     core::arch::naked_asm!(
         "1:",
         "auipc t0, %pcrel_hi({context})",
         "addi t0, t0, %pcrel_lo(1b)",
-        "ld t1, {env_entry_off}(t0)", // .env_entry
-        "beqz t1, 2f",
-        "ld t2, {env_string_off}(t0)", // .env_string
-        "sd t2, 0(t1)",
+        "addi t1, t0, {env_reverts_off}",
         "2:",
+        "ld t2, 0(t1)", // .entry
+        "beqz t2, 3f",
+        "ld t3, {env_string_off}(t1)", // .string
+        "sd t3, 0(t2)",
+        "addi t1, t1, {env_revert_size}",
+        "j 2b",
+        "3:",
         "ld t0, 0(t0)",
         "jr t0",
         context = sym TRAMPOLINE_CONTEXT,
-        env_entry_off = const TrampolineContext::ENV_ENTRY_OFFSET,
+        env_reverts_off = const TrampolineContext::ENV_REVERTS_OFFSET,
+        env_revert_size = const TrampolineContext::ENV_REVERT_SIZE,
+        env_string_off = const TrampolineContext::ENV_STRING_OFFSET,
+    )
+}
+
+#[cfg(all(feature = "entry_trampoline", target_arch = "s390x"))]
+pub const ENTRY_TRAMPOLINE: Option<unsafe extern "C" fn() -> !> = Some(entry_trampoline);
+
+// r14 carries the rtld_fini pointer to _start, so we only use r0,
+// r1 and r2, which is spilled to the context. r0 cannot be used as
+// a base register.
+#[cfg(all(feature = "entry_trampoline", target_arch = "s390x"))]
+#[unsafe(naked)]
+unsafe extern "C" fn entry_trampoline() -> ! {
+    core::arch::naked_asm!(
+        "larl %r1, {context}",
+        "stg %r2, {scratch_off}(%r1)",
+        "la %r2, {env_reverts_off}(%r1)",
+        "2:",
+        "lg %r1, 0(%r2)", // .entry
+        "ltgr %r1, %r1",
+        "jz 3f",
+        "lg %r0, {env_string_off}(%r2)", // .string
+        "stg %r0, 0(%r1)",
+        "la %r2, {env_revert_size}(%r2)",
+        "j 2b",
+        "3:",
+        "larl %r1, {context}",
+        "lg %r2, {scratch_off}(%r1)",
+        "lg %r1, 0(%r1)",
+        "br %r1",
+        context = sym TRAMPOLINE_CONTEXT,
+        scratch_off = const TrampolineContext::SCRATCH_OFFSET,
+        env_reverts_off = const TrampolineContext::ENV_REVERTS_OFFSET,
+        env_revert_size = const TrampolineContext::ENV_REVERT_SIZE,
+        env_string_off = const TrampolineContext::ENV_STRING_OFFSET,
+    )
+}
+
+#[cfg(all(feature = "entry_trampoline", target_arch = "loongarch64"))]
+pub const ENTRY_TRAMPOLINE: Option<unsafe extern "C" fn() -> !> = Some(entry_trampoline);
+
+#[cfg(all(feature = "entry_trampoline", target_arch = "loongarch64"))]
+#[unsafe(naked)]
+unsafe extern "C" fn entry_trampoline() -> ! {
+    core::arch::naked_asm!(
+        "la.pcrel $t0, {context}",
+        "addi.d $t1, $t0, {env_reverts_off}",
+        "2:",
+        "ld.d $t2, $t1, 0", // .entry
+        "beqz $t2, 3f",
+        "ld.d $t3, $t1, {env_string_off}", // .string
+        "st.d $t3, $t2, 0",
+        "addi.d $t1, $t1, {env_revert_size}",
+        "b 2b",
+        "3:",
+        "ld.d $t0, $t0, 0",
+        "jr $t0",
+        context = sym TRAMPOLINE_CONTEXT,
+        env_reverts_off = const TrampolineContext::ENV_REVERTS_OFFSET,
+        env_revert_size = const TrampolineContext::ENV_REVERT_SIZE,
+        env_string_off = const TrampolineContext::ENV_STRING_OFFSET,
+    )
+}
+
+#[cfg(all(feature = "entry_trampoline", target_arch = "powerpc64"))]
+pub const ENTRY_TRAMPOLINE: Option<unsafe extern "C" fn() -> !> = Some(entry_trampoline);
+
+// ld.so passes argc, argv, envp, auxv and rtld_fini in r3-r7, so we
+// only use r0, r11, r12 and ctr. The entry point gets its own address
+// in r12 (ELFv2 global entry) so that it can find its TOC.
+#[cfg(all(feature = "entry_trampoline", target_arch = "powerpc64"))]
+#[unsafe(naked)]
+unsafe extern "C" fn entry_trampoline() -> ! {
+    core::arch::naked_asm!(
+        "bcl 20, 31, 1f",
+        "1:",
+        "mflr 11",
+        "addis 11, 11, ({context} - 1b)@ha",
+        "addi 11, 11, ({context} - 1b)@l",
+        "ld 12, 0(11)",
+        "mtctr 12",
+        "addi 11, 11, {env_reverts_off}",
+        "2:",
+        "ld 12, 0(11)", // .entry
+        "cmpdi 12, 0",
+        "beq 3f",
+        "ld 0, {env_string_off}(11)", // .string
+        "std 0, 0(12)",
+        "addi 11, 11, {env_revert_size}",
+        "b 2b",
+        "3:",
+        "mfctr 12",
+        "bctr",
+        context = sym TRAMPOLINE_CONTEXT,
+        env_reverts_off = const TrampolineContext::ENV_REVERTS_OFFSET,
+        env_revert_size = const TrampolineContext::ENV_REVERT_SIZE,
         env_string_off = const TrampolineContext::ENV_STRING_OFFSET,
     )
 }
@@ -209,7 +770,12 @@ unsafe extern "C" fn entry_trampoline() -> ! {
 #[cfg(all(
     feature = "entry_trampoline",
     not(target_arch = "x86_64"),
+    not(target_arch = "x86"),
+    not(target_arch = "arm"),
     not(target_arch = "aarch64"),
-    not(target_arch = "riscv64")
+    not(target_arch = "riscv64"),
+    not(target_arch = "s390x"),
+    not(target_arch = "loongarch64"),
+    not(target_arch = "powerpc64")
 ))]
 pub const ENTRY_TRAMPOLINE: Option<unsafe extern "C" fn() -> !> = None;
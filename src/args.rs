@@ -2,12 +2,24 @@
 
 use core::ffi::{CStr, c_void};
 use core::mem;
+use core::ptr;
 use core::slice;
 
+use heapless::Vec as ArrayVec;
+
 use crate::arch::STACK_ALIGNMENT;
 use crate::auxv::AuxVec;
 use crate::support::explode;
-use crate::sys::new_slice_leak;
+use crate::sys::{Error, ErrorKind, new_slice_leak};
+
+/// The maximum number of environment variables that can be added.
+pub const MAX_EXTRA_ENV: usize = 32;
+
+/// The maximum number of environment variables that can be removed.
+pub const MAX_REMOVED_ENV: usize = 32;
+
+/// The maximum number of environment edits that can be reverted.
+pub const MAX_ENV_REVERTS: usize = 32;
 
 trait CStrExt {
     fn parse_env(&self) -> Option<(&[u8], &[u8])>;
@@ -25,7 +37,13 @@ pub struct Args {
     // The number of original environment variables.
     envc: usize,
 
-    extra_env: Option<*const u8>,
+    extra_env: ArrayVec<*const u8, MAX_EXTRA_ENV>,
+
+    // Indices of the original environment variables to drop.
+    removed_env: ArrayVec<usize, MAX_REMOVED_ENV>,
+
+    // Edits to revert before the program's entry point runs.
+    env_reverts: ArrayVec<EnvEdit, MAX_ENV_REVERTS>,
 }
 
 pub struct EnvIter<'args> {
@@ -42,6 +60,7 @@ pub struct EnvIter<'args> {
 /// Any methods that mutate the view will consume the `VarHandle`.
 #[derive(Debug)]
 pub struct VarHandle {
+    index: usize,
     ptr: *const *const u8,
     name: &'static [u8],
     value_c: &'static [u8],
@@ -53,21 +72,48 @@ pub struct StartContext {
     pub sp: *const c_void,
     pub argv: *const *const u8,
     pub envp: *const *const u8,
-    pub extra_env: Option<*const *const u8>,
+
+    /// The edits to revert, resolved to the final environment slots.
+    pub env_reverts: ArrayVec<EnvRevert, MAX_ENV_REVERTS>,
+}
+
+/// A slot in the environment.
+#[derive(Clone, Copy, Debug)]
+pub enum EnvSlot {
+    /// An original environment variable, by index.
+    Original(usize),
+
+    /// An added environment variable, by the index returned by
+    /// `Args::add_env()`.
+    Extra(usize),
 }
 
+/// A temporary edit of the environment.
+#[derive(Debug)]
 pub struct EnvEdit {
-    pub entry: *const *const u8,
+    pub slot: EnvSlot,
     pub old_string: *const u8,
 }
 
-struct StackShifter<'a> {
-    arg_slice: &'a mut [usize],
-    orig_idx: usize,
+/// An environment slot to restore in the entry trampoline.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct EnvRevert {
+    pub entry: *const *const u8,
+    pub string: *const u8,
+}
+
+/// Strings copied next to the new vectors.
+struct StringArea {
+    cur: *mut u8,
+    end: *mut u8,
+}
+
+struct StackBuilder<'a> {
+    stack: &'a mut [usize],
     idx: usize,
     idx_argv: Option<usize>,
     idx_envp: Option<usize>,
-    idx_extra_env: Option<usize>,
 }
 
 impl CStrExt for CStr {
@@ -94,7 +140,9 @@ impl Args {
                 envp,
                 envc,
                 auxv: AuxVec::from_raw(auxv),
-                extra_env: None,
+                extra_env: ArrayVec::new(),
+                removed_env: ArrayVec::new(),
+                env_reverts: ArrayVec::new(),
             }
         }
     }
@@ -110,6 +158,20 @@ impl Args {
         self.argc
     }
 
+    /// Returns an argument.
+    pub fn arg(&self, index: usize) -> Option<&'static CStr> {
+        if index >= self.argc {
+            return None;
+        }
+
+        let ptr = unsafe { *self.argv.add(index) };
+        if ptr.is_null() {
+            None
+        } else {
+            Some(unsafe { CStr::from_ptr(ptr.cast()) })
+        }
+    }
+
     /// Perform a handoff to the actual ld.so.
     ///
     /// The function must not return.
@@ -117,108 +179,220 @@ impl Args {
     where
         F: FnOnce(StartContext),
     {
-        if let Some(extra_env) = self.extra_env {
-            // Wiggle the stack to add the new environment
-            //
-            // <https://fasterthanli.me/content/series/making-our-own-executable-packer/part-12/assets/elf-stack.43b0caae88ae7ef5.svg>
-            //
-            // Since we are operating on an entirely separate stack, we can
-            // simply treat the old stack as a slice of usizes and shift
-            // stuff around in it with `copy_within`.
-            //
-            // TODO: Too overengineered - Simplify
-            log::info!("Shifting the stack to accomodate new environment variable");
-
-            let auxvc = self.auxv.count().unwrap();
-            let apparent_len = 1 + self.argc + 1 + self.envc + 1 + auxvc * 2 + 2;
-            let additional_len = 1;
-            let desired_len = apparent_len + additional_len;
-
-            // Does the stack actually look like what we expect?
-            let end_of_storage: *const usize =
-                unsafe { self.auxv.as_ptr().unwrap().add(2 * auxvc + 2) };
-            let start_of_storage: *const usize = unsafe { self.argv.sub(1).cast() };
-            let actual_len = unsafe { end_of_storage.offset_from(start_of_storage) };
-            if actual_len < 0 || actual_len as usize != apparent_len {
-                panic!(
-                    "Stack layout different from expected ({} vs {})",
-                    actual_len, apparent_len
-                );
-            }
-
-            // Now apply stack alignment
-            let new_start_of_storage_unpad =
-                unsafe { start_of_storage.sub(additional_len).cast_mut() };
-            let new_start_of_storage =
-                (new_start_of_storage_unpad as usize & !(STACK_ALIGNMENT - 1)) as *mut usize;
-            let padding_len = {
-                let pad_end = new_start_of_storage_unpad as *const u8;
-                let pad_start = new_start_of_storage as *const u8;
-                let bytes = unsafe { pad_end.offset_from(pad_start) };
-                if bytes < 0 || bytes as usize % mem::size_of::<*const usize>() != 0 {
-                    panic!("Padding not a multiple of pointers: {}", bytes);
-                }
-
-                bytes as usize / mem::size_of::<*const usize>()
-            };
-
-            let arg_slice = unsafe {
-                slice::from_raw_parts_mut(new_start_of_storage, desired_len + padding_len)
-            };
-            let mut shifter = StackShifter::new(arg_slice, additional_len + padding_len);
-
-            // We want it to look like this:
-            //
-            // [argc][argv][0][envp][newenv][0][auxv][last element][padding]
-            shifter.copy(1); // [argc]
-            shifter.mark_argv();
-            shifter.copy(self.argc + 1); // [argv][0]
-            shifter.mark_envp();
-            shifter.copy(self.envc); // [envp]
-            shifter.mark_extra_env();
-            shifter.push(extra_env as usize);
-            shifter.copy(1 + auxvc * 2 + 2); // [0][auxv][last element]
-
-            f(shifter.finalize());
+        let start = if !self.extra_env.is_empty() || !self.removed_env.is_empty() {
+            log::info!("Building new vectors to add or remove environment variables");
+            self.build_stack()
         } else {
             // No need to mess with the stack
-            log::info!("No new environment variable added - Not shifting the stack");
+            log::info!("No environment variable added or removed - Reusing the vectors");
             let sp = unsafe { self.argv.sub(1) };
-            f(StartContext {
+            let mut start = StartContext {
                 sp: sp.cast(),
                 argv: self.argv,
                 envp: self.envp,
-                extra_env: None,
-            });
-        }
+                env_reverts: ArrayVec::new(),
+            };
+            self.resolve_env_reverts(&mut start, |edit| edit.old_string);
+            start
+        };
+
+        f(start);
 
         // Nothing might work at this point
         explode("The handoff function returned");
     }
 
+    /// Builds new vectors with the edited environment.
+    ///
+    /// <https://fasterthanli.me/content/series/making-our-own-executable-packer/part-12/assets/elf-stack.43b0caae88ae7ef5.svg>
+    ///
+    /// We run on a separate stack, so the vectors are built from scratch
+    /// right below the original ones, where the program's stack would
+    /// have grown anyway. Nothing is assumed about how the original
+    /// vectors are laid out.
+    ///
+    /// argv, envp and the auxv keep pointing at the original strings,
+    /// which is where the kernel expects the arguments and environment
+    /// to be (e.g., for `/proc/<pid>/cmdline` and `setproctitle()`).
+    /// Only the strings nix-ld made up are copied next to the vectors.
+    fn build_stack(&self) -> StartContext {
+        let auxvc = self.auxv.count().unwrap();
+        let envc = self.envc - self.removed_env.len() + self.extra_env.len();
+        let len = 1 + self.argc + 1 + envc + 1 + auxvc * 2 + 2;
+
+        let top = unsafe { self.argv.sub(1) } as usize;
+        let strings_start = top - self.strings_size();
+        let mut strings = StringArea {
+            cur: strings_start as *mut u8,
+            end: top as *mut u8,
+        };
+
+        let sp = (strings_start - len * mem::size_of::<usize>()) & !(STACK_ALIGNMENT - 1);
+        let stack = unsafe { slice::from_raw_parts_mut(sp as *mut usize, len) };
+        let mut builder = StackBuilder::new(stack);
+
+        // [argc][argv][0][envp][newenv][0][auxv][AT_NULL][strings]
+        unsafe {
+            builder.push(self.argc);
+            builder.mark_argv();
+            for i in 0..self.argc {
+                builder.push(*self.argv.add(i) as usize);
+            }
+            builder.push(0);
+
+            builder.mark_envp();
+            for i in 0..self.envc {
+                if !self.removed_env.contains(&i) {
+                    builder.push(*self.envp.add(i) as usize);
+                }
+            }
+            for extra_env in &self.extra_env {
+                builder.push(strings.copy_cstr(*extra_env) as usize);
+            }
+            builder.push(0);
+
+            let auxv = self.auxv.as_ptr().unwrap();
+            for i in 0..(auxvc + 1) * 2 {
+                builder.push(*auxv.add(i));
+            }
+        }
+
+        let mut start = builder.finalize();
+        self.resolve_env_reverts(&mut start, |edit| match edit.slot {
+            EnvSlot::Original(_) => edit.old_string,
+            EnvSlot::Extra(_) => unsafe { strings.copy_cstr(edit.old_string) },
+        });
+        assert_eq!(strings.cur, strings.end, "String size mismatch");
+
+        start
+    }
+
+    /// Returns the size of the strings to copy next to new vectors.
+    ///
+    /// Original variables are restored to their original strings, so
+    /// only the strings of added variables and their reverts count.
+    fn strings_size(&self) -> usize {
+        let mut size = 0;
+        unsafe {
+            for extra_env in &self.extra_env {
+                size += cstr_size(*extra_env);
+            }
+            for edit in &self.env_reverts {
+                if let EnvSlot::Extra(_) = edit.slot {
+                    size += cstr_size(edit.old_string);
+                }
+            }
+        }
+        size
+    }
+
     /// Creates a new environment variable.
-    pub fn add_env<F>(&mut self, name: &str, value_len: usize, f: F) -> Result<*const u8, ()>
+    ///
+    /// Returns the index of the new variable in `StartContext::extra_env`.
+    pub fn add_env<F>(&mut self, name: &str, value_len: usize, f: F) -> Result<usize, Error>
     where
         F: FnOnce(&mut [u8]),
     {
-        if self.extra_env.is_some() {
-            return Err(());
+        if self.extra_env.is_full() {
+            return Err(ErrorKind::TooManyEnv.into());
         }
 
         let name_len = name.len();
         let whole_len = name_len + 1 + value_len;
-        let new_buf = new_slice_leak(whole_len + 1).unwrap();
+        let new_buf = new_slice_leak(whole_len + 1)?;
         new_buf[..name_len].copy_from_slice(name.as_bytes());
         new_buf[name_len] = b'=';
         new_buf[whole_len] = 0;
 
         f(&mut new_buf[name_len + 1..whole_len]);
 
-        self.extra_env = Some(new_buf.as_ptr());
-        Ok(new_buf.as_ptr())
+        self.extra_env.push(new_buf.as_ptr()).unwrap();
+        Ok(self.extra_env.len() - 1)
+    }
+
+    /// Removes an environment variable.
+    ///
+    /// The removal is permanent: The variable is hidden from both ld.so
+    /// and the program, as there is no slot to put it back into once
+    /// the stack is laid out. A variable with an edit to revert cannot
+    /// be removed.
+    pub fn remove_env(&mut self, var: VarHandle) -> Result<(), Error> {
+        if self.is_reverted(var.index) {
+            return Err(ErrorKind::RemovedEnv.into());
+        }
+
+        log::debug!("Removing env element {:?}", var.ptr);
+        self.removed_env
+            .push(var.index)
+            .map_err(|_| ErrorKind::TooManyEnv.into())
+    }
+
+    /// Reverts an edit before the program's entry point runs.
+    ///
+    /// This only has an effect with an entry trampoline. Edits to
+    /// removed variables are rejected, since they cannot be restored.
+    pub fn revert_env_on_entry(&mut self, edit: EnvEdit) -> Result<(), Error> {
+        if let EnvSlot::Original(i) = edit.slot
+            && self.removed_env.contains(&i)
+        {
+            return Err(ErrorKind::RemovedEnv.into());
+        }
+
+        self.env_reverts
+            .push(edit)
+            .map_err(|_| ErrorKind::TooManyEnv.into())
+    }
+
+    /// Returns whether an original environment variable has an edit
+    /// to revert.
+    fn is_reverted(&self, index: usize) -> bool {
+        self.env_reverts
+            .iter()
+            .any(|edit| matches!(edit.slot, EnvSlot::Original(i) if i == index))
+    }
+
+    /// Returns the value of an environment variable without taking
+    /// a handle to it.
+    pub fn get_env(&self, name: &[u8]) -> Option<&'static CStr> {
+        (0..self.envc).find_map(|i| {
+            let env = unsafe { CStr::from_ptr((*self.envp.add(i)).cast()) };
+            match env.parse_env() {
+                Some((env_name, value_c)) if env_name == name => {
+                    CStr::from_bytes_with_nul(value_c).ok()
+                }
+                _ => None,
+            }
+        })
+    }
+
+    /// Resolves the edits to revert to the slots in the final environment.
+    ///
+    /// `old_string` returns the string to restore for each edit.
+    fn resolve_env_reverts<F>(&self, start: &mut StartContext, mut old_string: F)
+    where
+        F: FnMut(&EnvEdit) -> *const u8,
+    {
+        let kept_envc = self.envc - self.removed_env.len();
+        for edit in &self.env_reverts {
+            let index = match edit.slot {
+                EnvSlot::Original(i) => {
+                    i - self
+                        .removed_env
+                        .iter()
+                        .filter(|&&removed| removed < i)
+                        .count()
+                }
+                EnvSlot::Extra(i) => kept_envc + i,
+            };
+            let revert = EnvRevert {
+                entry: unsafe { start.envp.add(index) },
+                string: old_string(edit),
+            };
+            start.env_reverts.push(revert).unwrap();
+        }
     }
 
-    pub fn iter_env(&mut self) -> Option<EnvIter> {
+    pub fn iter_env(&mut self) -> Option<EnvIter<'_>> {
         if self.env_iterated {
             return None;
         }
@@ -249,8 +423,14 @@ impl Iterator for EnvIter<'_> {
 
         let env = unsafe { core::ffi::CStr::from_ptr(pptr.cast()) };
         if let Some((name, value_c)) = env.parse_env() {
+            let index = self.index;
             self.index += 1;
-            Some(VarHandle { ptr, name, value_c })
+            Some(VarHandle {
+                index,
+                ptr,
+                name,
+                value_c,
+            })
         } else {
             // Bad environment
             self.ended = true;
@@ -261,28 +441,20 @@ impl Iterator for EnvIter<'_> {
 
 impl VarHandle {
     /// Returns the name as bytes.
-    pub fn name(&self) -> &[u8] {
+    pub fn name(&self) -> &'static [u8] {
         self.name
     }
 
     /// Returns the value as bytes, without the trailing NUL.
-    pub fn value(&self) -> &[u8] {
+    pub fn value(&self) -> &'static [u8] {
         &self.value_c[..self.value_c.len() - 1]
     }
 
     /// Returns the value as a NUL-terminated CStr.
-    pub fn value_cstr(&self) -> &CStr {
+    pub fn value_cstr(&self) -> &'static CStr {
         core::ffi::CStr::from_bytes_with_nul(self.value_c).unwrap()
     }
 
-    /// Rename the variable without changing its value.
-    pub fn rename(self, new_name: &str) -> EnvEdit {
-        let value_len = self.value().len();
-        self.edit(Some(new_name), value_len, |old, new| {
-            new.copy_from_slice(old);
-        })
-    }
-
     /// Edits the value.
     ///
     /// The function will take the original value and must fill the
@@ -308,7 +480,7 @@ impl VarHandle {
         );
 
         EnvEdit {
-            entry: self.ptr,
+            slot: EnvSlot::Original(self.index),
             old_string: old_buf,
         }
     }
@@ -325,31 +497,36 @@ impl VarHandle {
     }
 }
 
-impl<'a> StackShifter<'a> {
-    fn new(arg_slice: &'a mut [usize], orig_idx: usize) -> Self {
+impl StringArea {
+    /// Copies bytes, returning the new address.
+    unsafe fn copy(&mut self, src: *const u8, len: usize) -> *const u8 {
+        if src.is_null() {
+            return src;
+        }
+
+        assert!(len <= self.end as usize - self.cur as usize);
+        let dst = self.cur;
+        unsafe {
+            ptr::copy_nonoverlapping(src, dst, len);
+            self.cur = dst.add(len);
+        }
+        dst
+    }
+
+    /// Copies a NUL-terminated string, returning the new address.
+    unsafe fn copy_cstr(&mut self, src: *const u8) -> *const u8 {
+        unsafe { self.copy(src, cstr_size(src)) }
+    }
+}
+
+impl<'a> StackBuilder<'a> {
+    fn new(stack: &'a mut [usize]) -> Self {
         Self {
-            arg_slice,
-            orig_idx,
+            stack,
             idx: 0,
             idx_argv: None,
             idx_envp: None,
-            idx_extra_env: None,
-        }
-    }
-
-    #[inline(always)]
-    fn copy(&mut self, count: usize) {
-        self.arg_slice
-            .copy_within(self.orig_idx..self.orig_idx + count, self.idx);
-        for i in 0..count {
-            log::trace!(
-                "Copied [{}]: {:x}",
-                self.idx + i,
-                self.arg_slice[self.idx + i]
-            );
         }
-        self.orig_idx += count;
-        self.idx += count;
     }
 
     #[inline(always)]
@@ -362,15 +539,10 @@ impl<'a> StackShifter<'a> {
         self.idx_envp = Some(self.idx);
     }
 
-    #[inline(always)]
-    fn mark_extra_env(&mut self) {
-        self.idx_extra_env = Some(self.idx);
-    }
-
     #[inline(always)]
     fn push(&mut self, value: usize) {
-        self.arg_slice[self.idx] = value;
-        log::trace!(" Added [{}]: {:x}", self.idx, value);
+        self.stack[self.idx] = value;
+        log::trace!("Pushed [{}]: {:x}", self.idx, value);
         self.idx += 1;
     }
 
@@ -378,19 +550,26 @@ impl<'a> StackShifter<'a> {
     fn finalize(self) -> StartContext {
         let idx_argv = self.idx_argv.expect("Must have argv");
         let idx_envp = self.idx_envp.expect("Must have envp");
-        let extra_env = self
-            .idx_extra_env
-            .map(|idx| (&self.arg_slice[idx] as *const usize).cast());
+        assert_eq!(self.idx, self.stack.len(), "Stack size mismatch");
 
         StartContext {
-            sp: self.arg_slice.as_ptr().cast(),
-            argv: (&self.arg_slice[idx_argv] as *const usize).cast(),
-            envp: (&self.arg_slice[idx_envp] as *const usize).cast(),
-            extra_env,
+            sp: self.stack.as_ptr().cast(),
+            argv: (&self.stack[idx_argv] as *const usize).cast(),
+            envp: (&self.stack[idx_envp] as *const usize).cast(),
+            env_reverts: ArrayVec::new(),
         }
     }
 }
 
+/// Returns the size of a NUL-terminated string, including the NUL.
+unsafe fn cstr_size(s: *const u8) -> usize {
+    if s.is_null() {
+        0
+    } else {
+        unsafe { CStr::from_ptr(s.cast()).count_bytes() + 1 }
+    }
+}
+
 unsafe fn count_env(envp: *const *const u8) -> (usize, *const usize) {
     unsafe {
         let mut envc = 0;
@@ -1,6 +1,6 @@
 //! auxv wrangling.
 
-use core::ffi::c_void;
+use core::ffi::{CStr, c_char, c_void};
 use core::marker::PhantomData;
 use core::ops::Deref;
 
@@ -12,6 +12,9 @@ pub const AT_PHNUM: usize = 5;
 pub const AT_PAGESZ: usize = 6;
 pub const AT_BASE: usize = 7;
 pub const AT_ENTRY: usize = 9;
+pub const AT_SECURE: usize = 23;
+pub const AT_RANDOM: usize = 25;
+pub const AT_EXECFN: usize = 31;
 
 #[derive(Debug, Default)]
 pub struct AuxVec {
@@ -23,6 +26,9 @@ pub struct AuxVec {
     pub at_phent: Option<Entry>,
     pub at_phnum: Option<Entry>,
     pub at_pagesz: Option<Entry>,
+    pub at_execfn: Option<Entry<*const c_char>>,
+    pub at_secure: Option<Entry>,
+    pub at_random: Option<Entry<*const u8>>,
 }
 
 #[derive(Debug)]
@@ -42,6 +48,17 @@ pub struct AuxVecIter<'a> {
 }
 
 impl AuxVec {
+    /// Finds the auxiliary vector after the environment.
+    pub unsafe fn from_envp(envp: *const *const u8) -> Self {
+        unsafe {
+            let mut cur = envp;
+            while !(*cur).is_null() {
+                cur = cur.add(1);
+            }
+            Self::from_raw(cur.add(1) as *const usize)
+        }
+    }
+
     pub unsafe fn from_raw(ptr: *const usize) -> Self {
         let mut auxv = Self {
             ptr: Some(ptr),
@@ -54,6 +71,9 @@ impl AuxVec {
         let mut at_phent = None;
         let mut at_phnum = None;
         let mut at_pagesz = None;
+        let mut at_execfn = None;
+        let mut at_secure = None;
+        let mut at_random = None;
         let mut auxvc = 0;
 
         for entry in auxv.iter() {
@@ -64,6 +84,9 @@ impl AuxVec {
                 AT_PHENT => at_phent = Some(entry.steal()),
                 AT_PHNUM => at_phnum = Some(entry.steal()),
                 AT_PAGESZ => at_pagesz = Some(entry.steal()),
+                AT_EXECFN => at_execfn = Some(entry.steal()),
+                AT_SECURE => at_secure = Some(entry.steal()),
+                AT_RANDOM => at_random = Some(entry.steal()),
                 _ => {}
             }
             auxvc += 1;
@@ -75,10 +98,33 @@ impl AuxVec {
         auxv.at_phent = at_phent;
         auxv.at_phnum = at_phnum;
         auxv.at_pagesz = at_pagesz;
+        auxv.at_execfn = at_execfn;
+        auxv.at_secure = at_secure;
+        auxv.at_random = at_random;
         auxv.auxvc = Some(auxvc);
         auxv
     }
 
+    /// Returns the path the program was executed with.
+    pub fn execfn(&self) -> Option<&'static CStr> {
+        let execfn = self.at_execfn.as_ref()?.value();
+        if execfn.is_null() {
+            None
+        } else {
+            Some(unsafe { CStr::from_ptr(execfn) })
+        }
+    }
+
+    /// Returns whether the program runs in secure-execution mode.
+    ///
+    /// This is the case for setuid/setgid programs and programs with
+    /// file capabilities.
+    pub fn is_secure(&self) -> bool {
+        self.at_secure
+            .as_ref()
+            .is_some_and(|secure| secure.value() != 0)
+    }
+
     pub fn as_ptr(&self) -> Option<*const usize> {
         self.ptr
     }
@@ -87,7 +133,7 @@ impl AuxVec {
         self.auxvc
     }
 
-    pub fn iter(&self) -> AuxVecIter {
+    pub fn iter(&self) -> AuxVecIter<'_> {
         AuxVecIter {
             auxv: self,
             index: 0,
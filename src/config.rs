@@ -0,0 +1,748 @@
+//! Config files.
+//!
+//! Config files use the same names as the environment variables
+//! they stand in for, one `NAME = value` pair per line:
+//!
+//! ```text
+//! # /etc/nix-ld/config
+//! NIX_LD = /run/current-system/sw/share/nix-ld/lib/ld.so
+//! NIX_LD_LIBRARY_PATH = /run/current-system/sw/share/nix-ld/lib
+//! NIX_LD_LIBRARY_PATH_aarch64_linux = /some/other/lib
+//! ```
+//!
+//! Lines starting with `#` are comments. Like in the environment,
+//! the system-specific variants take precedence over the generic ones.
+//!
+//! Settings for specific programs go in profiles. A profile starts
+//! with a `[name]` line and applies to programs matching any of its
+//! `match-*` keys. Profiles can also set extra environment variables:
+//!
+//! ```text
+//! [vivado]
+//! match-path = /opt/Xilinx/*/bin/*
+//! match-name = vivado
+//! match-build-id = 5c8f3b1e0d2a4c6b8a9e7f1d3c5b7a9e1f2d4c6b
+//! NIX_LD_LIBRARY_PATH = /opt/Xilinx/lib
+//! env.LC_ALL = C
+//! ```
+//!
+//! The system-wide config file can also set policies outside of any
+//! profile. `trusted-loader-prefix` restricts the loaders nix-ld is
+//! willing to use (see [`crate::trust`]), and `allowlist` restricts the
+//! programs it is willing to run (see [`crate::allowlist`]).
+
+use core::ffi::CStr;
+
+use heapless::Vec as ArrayVec;
+
+use crate::arch::{NIX_LD_LIBRARY_PATH_SYSTEM_ENV_BYTES, NIX_LD_SYSTEM_ENV_BYTES};
+use crate::sys::{
+    self, Error as IoError, ErrorKind, File, PathBuf, Read, S_ISVTX, S_IWGRP, S_IWOTH, STATX_MODE,
+    STATX_UID, errno,
+};
+use crate::target::{self, Target};
+use crate::trust::MAX_TRUSTED_PREFIXES;
+
+/// The system-wide config file.
+pub const SYSTEM_CONFIG: &CStr = c"/etc/nix-ld/config";
+
+/// The per-user config file, relative to `$XDG_CONFIG_HOME`.
+pub const USER_CONFIG: &[u8] = b"/nix-ld/config";
+
+/// The per-user config file, relative to `$HOME`.
+pub const USER_CONFIG_FALLBACK: &[u8] = b"/.config/nix-ld/config";
+
+/// The name of sidecar files.
+///
+/// `foo.nix-ld` applies to the program `foo`, and `.nix-ld` applies
+/// to all programs in the same directory.
+const SIDECAR: &[u8] = b".nix-ld";
+
+/// The extended attribute holding the loader of a program.
+const XATTR_LOADER: &CStr = c"user.nix-ld.loader";
+
+/// The extended attribute holding the library path of a program.
+const XATTR_LIBRARY_PATH: &CStr = c"user.nix-ld.library-path";
+
+/// The extended attribute holding extra environment variables for a
+/// program, one `NAME=value` pair per line.
+const XATTR_ENV: &CStr = c"user.nix-ld.env";
+
+/// The maximum number of profiles.
+pub const MAX_PROFILES: usize = 32;
+
+/// The maximum number of extra environment variables in a profile.
+pub const MAX_PROFILE_ENV: usize = 16;
+
+/// The maximum number of `match-*` keys in a profile.
+const MAX_MATCHES: usize = 8;
+
+/// Maximum size of a config file.
+const MAX_CONFIG_SIZE: usize = 64 * 1024;
+
+/// Settings from a config file.
+#[derive(Debug, Default)]
+pub struct Config {
+    /// Settings outside of any profile.
+    pub settings: Settings,
+    pub profiles: ArrayVec<Profile, MAX_PROFILES>,
+
+    /// Policies from the system-wide config file.
+    pub policy: Policy,
+}
+
+/// Policies restricting what nix-ld will do.
+#[derive(Debug, Default)]
+pub struct Policy {
+    /// Prefixes under which loaders are trusted.
+    pub trusted_loader_prefixes: ArrayVec<&'static [u8], MAX_TRUSTED_PREFIXES>,
+
+    /// The allowlist of programs that can be run.
+    pub allowlist: Option<&'static CStr>,
+}
+
+/// A set of settings.
+///
+/// All values are NUL-terminated and live until the end of the
+/// process.
+#[derive(Clone, Debug, Default)]
+pub struct Settings {
+    pub nix_ld: Option<&'static CStr>,
+    pub nix_ld_library_path: Option<&'static CStr>,
+    pub nix_ld_log: Option<&'static CStr>,
+
+    /// Whether to drop library directories that don't exist.
+    pub skip_missing_dirs: Option<bool>,
+
+    pub env: ArrayVec<EnvVar, MAX_PROFILE_ENV>,
+}
+
+/// An extra environment variable.
+#[derive(Clone, Debug)]
+pub struct EnvVar {
+    pub name: &'static str,
+    pub value: &'static CStr,
+}
+
+/// Settings for specific programs.
+#[derive(Debug)]
+pub struct Profile {
+    pub name: &'static [u8],
+    matches: ArrayVec<Match, MAX_MATCHES>,
+    pub settings: Settings,
+}
+
+#[derive(Debug)]
+enum Match {
+    /// A glob matched against the real path of the program.
+    Path(&'static [u8]),
+
+    /// A glob matched against the basename of `argv[0]`.
+    Name(&'static [u8]),
+
+    /// A hex-encoded GNU build-id.
+    BuildId(&'static [u8]),
+}
+
+/// A section of the config file being parsed.
+struct Section {
+    profile_name: Option<&'static [u8]>,
+    matches: ArrayVec<Match, MAX_MATCHES>,
+    settings: Settings,
+    nix_ld_system: Option<&'static CStr>,
+    nix_ld_library_path_system: Option<&'static CStr>,
+}
+
+impl Config {
+    /// Loads a config file.
+    ///
+    /// A missing file is not an error and results in an empty config.
+    pub fn load(path: &CStr) -> Self {
+        Self::try_load(path).unwrap_or_default()
+    }
+
+    /// Loads a config file if it exists.
+    pub fn try_load(path: &CStr) -> Option<Self> {
+        Self::try_load_owned_by(path, None)
+    }
+
+    /// Loads a config file if it exists and, with `owner`, is owned
+    /// by root or that user and not writable by group or others.
+    pub fn try_load_owned_by(path: &CStr, owner: Option<u32>) -> Option<Self> {
+        match Self::read(path, owner) {
+            Ok(config) => config,
+            Err(e) if e == errno::ENOENT || e == errno::ENOTDIR => None,
+            Err(e) => {
+                log::warn!("Failed to read config file {path:?}: {e}");
+                None
+            }
+        }
+    }
+
+    /// Loads a config file that must be readable if it exists.
+    ///
+    /// A missing file results in an empty config, but any other error
+    /// is returned instead of being ignored.
+    pub fn load_strict(path: &CStr) -> Result<Self, IoError> {
+        match Self::read(path, None) {
+            Ok(config) => Ok(config.unwrap_or_default()),
+            Err(e) if e == errno::ENOENT => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Reads and parses a config file, returning `None` if `owner` is
+    /// set and the file fails the ownership checks.
+    fn read(path: &CStr, owner: Option<u32>) -> Result<Option<Self>, IoError> {
+        let mut file = File::open_cstr(path)?;
+        if let Some(owner) = owner {
+            let stat = file.statx(STATX_UID | STATX_MODE)?;
+            let uid = stat.stx_uid;
+            let mode = u32::from(stat.stx_mode);
+            if uid != 0 && uid != owner {
+                log::warn!("Ignoring {path:?} owned by uid {uid} instead of root or {owner}");
+                return Ok(None);
+            }
+            if mode & (S_IWGRP | S_IWOTH) != 0 {
+                log::warn!("Ignoring {path:?} writable by group or others (mode {mode:o})");
+                return Ok(None);
+            }
+        }
+
+        let buf = read_file_to_leaked(&mut file, path)?;
+        log::info!("Loaded config file {path:?}");
+        Ok(Some(Self::parse(buf)))
+    }
+
+    /// Parses a config file in place.
+    ///
+    /// The buffer must have at least one byte of spare space after the
+    /// file contents for the last NUL terminator.
+    pub fn parse(buf: &'static mut [u8]) -> Self {
+        let mut config = Self::default();
+        let buf = terminate_lines(buf);
+        let mut section = Section::new(None);
+
+        for line in buf.split(|b| *b == 0) {
+            let line = line.trim_ascii_start();
+            if line.is_empty() || line.starts_with(b"#") {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix(b"[").and_then(|l| l.strip_suffix(b"]")) {
+                config.finish_section(section);
+                section = Section::new(Some(name.trim_ascii()));
+                continue;
+            }
+
+            let Some(equal) = line.iter().position(|b| *b == b'=') else {
+                log::warn!("Ignoring malformed config line {:?}", AsStr(line));
+                continue;
+            };
+
+            let name = line[..equal].trim_ascii();
+            let value = line[equal + 1..].trim_ascii_start();
+
+            // Safety: Each line is followed by a NUL in the buffer
+            let value = unsafe { CStr::from_ptr(value.as_ptr().cast()) };
+
+            if section.profile_name.is_none() && config.policy.set(name, value) {
+                continue;
+            }
+
+            section.set(name, value);
+        }
+
+        config.finish_section(section);
+        config
+    }
+
+    /// Overrides the settings with those set in another config.
+    ///
+    /// Profiles in the other config are matched first. Policies can only
+    /// be set in the system-wide config file, so those in the other config
+    /// are ignored.
+    pub fn merge(&mut self, other: Self) {
+        self.settings.merge(other.settings);
+
+        if other.policy.is_set() {
+            log::warn!("Policies are only allowed in the system-wide config - Ignoring");
+        }
+
+        let mut profiles = other.profiles;
+        for profile in self.profiles.drain(..) {
+            if let Err(profile) = profiles.push(profile) {
+                log::warn!("Too many profiles - Ignoring {:?}", AsStr(profile.name));
+            }
+        }
+        self.profiles = profiles;
+    }
+
+    /// Returns the first profile matching the program.
+    pub fn find_profile(&self, target: &Target) -> Option<&Profile> {
+        // Only resolve the real path if a profile needs it
+        let real_path = self
+            .profiles
+            .iter()
+            .any(Profile::matches_path)
+            .then(|| match target::real_path() {
+                Ok(path) => Some(path),
+                Err(e) => {
+                    log::warn!("Couldn't resolve the program for match-path: {e}");
+                    None
+                }
+            })
+            .flatten();
+
+        self.profiles
+            .iter()
+            .find(|profile| profile.matches(target, real_path.as_deref()))
+    }
+
+    fn finish_section(&mut self, section: Section) {
+        let (profile_name, matches, settings) = section.finish();
+
+        let Some(name) = profile_name else {
+            if !matches.is_empty() || !settings.env.is_empty() {
+                log::warn!("match-* and env.* are only allowed in profiles - Ignoring");
+            }
+            self.settings.merge(Settings {
+                env: ArrayVec::new(),
+                ..settings
+            });
+            return;
+        };
+
+        if matches.is_empty() {
+            log::warn!("Profile {:?} has no match-* keys - Ignoring", AsStr(name));
+            return;
+        }
+
+        let profile = Profile {
+            name,
+            matches,
+            settings,
+        };
+        if self.profiles.push(profile).is_err() {
+            log::warn!("Too many profiles - Ignoring {:?}", AsStr(name));
+        }
+    }
+}
+
+impl Policy {
+    /// Sets a policy if the key is one.
+    fn set(&mut self, name: &[u8], value: &'static CStr) -> bool {
+        match name {
+            b"trusted-loader-prefix" => {
+                if self.trusted_loader_prefixes.push(value.to_bytes()).is_err() {
+                    log::warn!("Too many trusted prefixes - Ignoring {value:?}");
+                }
+            }
+            b"allowlist" => {
+                self.allowlist = Some(value);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Returns whether any policy is set.
+    fn is_set(&self) -> bool {
+        !self.trusted_loader_prefixes.is_empty() || self.allowlist.is_some()
+    }
+}
+
+impl Settings {
+    /// Overrides the settings with those set in another set of settings.
+    pub fn merge(&mut self, other: Self) {
+        self.nix_ld = other.nix_ld.or(self.nix_ld);
+        self.nix_ld_library_path = other.nix_ld_library_path.or(self.nix_ld_library_path);
+        self.nix_ld_log = other.nix_ld_log.or(self.nix_ld_log);
+        self.skip_missing_dirs = other.skip_missing_dirs.or(self.skip_missing_dirs);
+
+        for var in other.env {
+            if let Some(existing) = self.env.iter_mut().find(|v| v.name == var.name) {
+                existing.value = var.value;
+            } else if let Err(var) = self.env.push(var) {
+                log::warn!("Too many env.* keys - Ignoring {}", var.name);
+            }
+        }
+    }
+
+    /// Adds an extra environment variable.
+    fn set_env(&mut self, name: &'static [u8], value: &'static CStr) {
+        // Variables we handle ourselves cannot be overridden this way
+        let name = match core::str::from_utf8(name) {
+            Ok(name)
+                if !name.is_empty()
+                    && !name.contains('=')
+                    && !name.starts_with("NIX_LD")
+                    && name != "LD_LIBRARY_PATH" =>
+            {
+                name
+            }
+            _ => {
+                log::warn!("Ignoring invalid variable name {:?}", AsStr(name));
+                return;
+            }
+        };
+
+        if self.env.push(EnvVar { name, value }).is_err() {
+            log::warn!("Too many env.* keys - Ignoring {name}");
+        }
+    }
+}
+
+impl Profile {
+    /// Returns whether the profile has a `match-path` key.
+    fn matches_path(&self) -> bool {
+        self.matches.iter().any(|m| matches!(m, Match::Path(_)))
+    }
+
+    /// Returns whether the profile applies to a program.
+    ///
+    /// `real_path` is the resolved path of the program, which is only
+    /// needed if [`Self::matches_path`] is true.
+    pub fn matches(&self, target: &Target, real_path: Option<&[u8]>) -> bool {
+        self.matches.iter().any(|m| match *m {
+            Match::Path(pattern) => real_path.is_some_and(|path| glob_match(pattern, path)),
+            Match::Name(pattern) => target.name.is_some_and(|name| glob_match(pattern, name)),
+            Match::BuildId(hex) => target.build_id.is_some_and(|id| hex_eq(hex, id)),
+        })
+    }
+}
+
+impl Section {
+    fn new(profile_name: Option<&'static [u8]>) -> Self {
+        Self {
+            profile_name,
+            matches: ArrayVec::new(),
+            settings: Settings::default(),
+            nix_ld_system: None,
+            nix_ld_library_path_system: None,
+        }
+    }
+
+    fn set(&mut self, name: &'static [u8], value: &'static CStr) {
+        let m = match name {
+            NIX_LD_SYSTEM_ENV_BYTES => {
+                self.nix_ld_system = Some(value);
+                return;
+            }
+            b"NIX_LD" => {
+                self.settings.nix_ld = Some(value);
+                return;
+            }
+            NIX_LD_LIBRARY_PATH_SYSTEM_ENV_BYTES => {
+                self.nix_ld_library_path_system = Some(value);
+                return;
+            }
+            b"NIX_LD_LIBRARY_PATH" => {
+                self.settings.nix_ld_library_path = Some(value);
+                return;
+            }
+            b"NIX_LD_LOG" => {
+                self.settings.nix_ld_log = Some(value);
+                return;
+            }
+            b"skip-missing-dirs" => {
+                match value.to_bytes() {
+                    b"true" => self.settings.skip_missing_dirs = Some(true),
+                    b"false" => self.settings.skip_missing_dirs = Some(false),
+                    _ => log::warn!("Ignoring invalid skip-missing-dirs value {value:?}"),
+                }
+                return;
+            }
+            b"match-path" => Match::Path(value.to_bytes()),
+            b"match-name" => Match::Name(value.to_bytes()),
+            b"match-build-id" => Match::BuildId(value.to_bytes()),
+            _ => {
+                if let Some(env_name) = name.strip_prefix(b"env.") {
+                    self.settings.set_env(env_name, value);
+                } else {
+                    log::warn!("Ignoring unknown config key {:?}", AsStr(name));
+                }
+                return;
+            }
+        };
+
+        if self.matches.push(m).is_err() {
+            log::warn!("Too many match-* keys - Ignoring {:?}", AsStr(name));
+        }
+    }
+
+    fn finish(
+        mut self,
+    ) -> (
+        Option<&'static [u8]>,
+        ArrayVec<Match, MAX_MATCHES>,
+        Settings,
+    ) {
+        self.settings.nix_ld = self.nix_ld_system.or(self.settings.nix_ld);
+        self.settings.nix_ld_library_path = self
+            .nix_ld_library_path_system
+            .or(self.settings.nix_ld_library_path);
+        (self.profile_name, self.matches, self.settings)
+    }
+}
+
+/// Loads the sidecar file of a program.
+///
+/// `foo.nix-ld` next to the program is tried first, then `.nix-ld` in
+/// the directory of the program and each of its parents. Only the
+/// first one found is used.
+///
+/// The search stops at the first directory that other users could
+/// have put a sidecar file in: one that is not owned by root or the
+/// owner of the program, or that is writable by group or others
+/// without the sticky bit. Sidecar files themselves must be owned by
+/// root or the owner of the program and not be writable by others.
+pub fn load_sidecar(program: &CStr) -> Option<Config> {
+    let owner = match sys::stat(program, STATX_UID) {
+        Ok(stat) => stat.stx_uid,
+        Err(e) => {
+            log::warn!("Not loading sidecar files: {e}");
+            return None;
+        }
+    };
+
+    let try_load = |dir: &[u8], name: &[u8]| {
+        let path = join_path(dir, name)?;
+        let path = CStr::from_bytes_with_nul(&path).ok()?;
+        let config = Config::try_load_owned_by(path, Some(owner))?;
+        if !config.profiles.is_empty() {
+            log::warn!("Profiles are not allowed in sidecar files - Ignoring");
+        }
+        Some(config)
+    };
+
+    let program = program.to_bytes();
+
+    // A relative path without any slashes is in the current directory
+    let mut dir = match program.iter().rposition(|b| *b == b'/') {
+        Some(slash) => &program[..=slash],
+        None => b"",
+    };
+
+    if !is_sidecar_dir_trusted(dir, owner) {
+        return None;
+    }
+    if let Some(config) = try_load(program, SIDECAR) {
+        return Some(config);
+    }
+
+    loop {
+        if let Some(config) = try_load(dir, SIDECAR) {
+            return Some(config);
+        }
+
+        // Stop at the root or the start of a relative path
+        let slash = dir[..dir.len().saturating_sub(1)]
+            .iter()
+            .rposition(|b| *b == b'/')?;
+        let parent = &dir[..=slash];
+        if !is_sidecar_dir_trusted(parent, owner) {
+            return None;
+        }
+        dir = parent;
+    }
+}
+
+/// Returns whether only root or `owner` can create files in a directory.
+fn is_sidecar_dir_trusted(dir: &[u8], owner: u32) -> bool {
+    let Some(path) = join_path(dir, b".") else {
+        return false;
+    };
+    let path = CStr::from_bytes_with_nul(&path).unwrap();
+
+    let stat = match sys::stat(path, STATX_UID | STATX_MODE) {
+        Ok(stat) => stat,
+        Err(e) => {
+            log::warn!("Not searching {path:?} for sidecar files: {e}");
+            return false;
+        }
+    };
+
+    let uid = stat.stx_uid;
+    let mode = u32::from(stat.stx_mode);
+    if uid != 0 && uid != owner {
+        log::debug!("Not searching {path:?} for sidecar files: Owned by uid {uid}");
+        false
+    } else if mode & (S_IWGRP | S_IWOTH) != 0 && mode & S_ISVTX == 0 {
+        log::debug!("Not searching {path:?} for sidecar files: Writable by group or others");
+        false
+    } else {
+        true
+    }
+}
+
+/// Loads the settings in the extended attributes of a program.
+pub fn load_xattrs(program: &CStr) -> Settings {
+    let mut settings = Settings::default();
+
+    let read = |name: &CStr| match sys::getxattr_leak(program, name) {
+        Ok(buf) => {
+            log::info!("Loaded extended attribute {name:?}");
+            Some(terminate_lines(buf))
+        }
+        Err(e) if e == errno::ENODATA || e == errno::EOPNOTSUPP => None,
+        Err(e) => {
+            log::warn!("Failed to read extended attribute {name:?}: {e}");
+            None
+        }
+    };
+
+    // Only the first line is used for single values
+    let first_line = |buf: &'static [u8]| {
+        CStr::from_bytes_until_nul(buf)
+            .ok()
+            .filter(|value| !value.is_empty())
+    };
+
+    if let Some(buf) = read(XATTR_LOADER) {
+        settings.nix_ld = first_line(buf);
+    }
+
+    if let Some(buf) = read(XATTR_LIBRARY_PATH) {
+        settings.nix_ld_library_path = first_line(buf);
+    }
+
+    if let Some(buf) = read(XATTR_ENV) {
+        for line in buf.split(|b| *b == 0) {
+            let line = line.trim_ascii_start();
+            if line.is_empty() {
+                continue;
+            }
+
+            let Some(equal) = line.iter().position(|b| *b == b'=') else {
+                log::warn!("Ignoring malformed variable {:?}", AsStr(line));
+                continue;
+            };
+
+            // Safety: Each line is followed by a NUL in the buffer
+            let value = unsafe { CStr::from_ptr(line[equal + 1..].as_ptr().cast()) };
+            settings.set_env(&line[..equal], value);
+        }
+    }
+
+    settings
+}
+
+/// NUL-terminates each line right after its value.
+///
+/// This allows values to be handed out as CStrs without copying. The
+/// buffer must end with a NUL or have one byte of spare space after the
+/// last line.
+pub fn terminate_lines(buf: &'static mut [u8]) -> &'static [u8] {
+    let mut line_start = 0;
+    for i in 0..buf.len() {
+        if buf[i] == b'\n' || buf[i] == 0 {
+            let mut end = i;
+            while end > line_start && buf[end - 1].is_ascii_whitespace() {
+                end -= 1;
+            }
+            buf[end..=i].fill(0);
+            line_start = i + 1;
+        }
+    }
+    buf
+}
+
+/// Joins a directory and a relative path into a NUL-terminated buffer.
+pub fn join_path(dir: &[u8], rel: &[u8]) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    path.extend_from_slice(dir).ok()?;
+    path.extend_from_slice(rel).ok()?;
+    path.push(0).ok()?;
+    Some(path)
+}
+
+/// Matches a glob pattern.
+///
+/// `*` matches any sequence of bytes including `/`, and `?` matches
+/// any single byte.
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let mut p = 0;
+    let mut t = 0;
+
+    // Where to resume when the last `*` needs to match one more byte
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                p += 1;
+                backtrack = Some((p, t));
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_t)) => {
+                    p = star_p;
+                    t = star_t + 1;
+                    backtrack = Some((star_p, t));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+/// Compares a hex string with bytes, ignoring case.
+pub fn hex_eq(hex: &[u8], bytes: &[u8]) -> bool {
+    fn nibble(c: u8) -> Option<u8> {
+        match c {
+            b'0'..=b'9' => Some(c - b'0'),
+            b'a'..=b'f' => Some(c - b'a' + 10),
+            b'A'..=b'F' => Some(c - b'A' + 10),
+            _ => None,
+        }
+    }
+
+    hex.len() == bytes.len() * 2
+        && hex.chunks_exact(2).zip(bytes).all(|(pair, byte)| {
+            match (nibble(pair[0]), nibble(pair[1])) {
+                (Some(hi), Some(lo)) => hi << 4 | lo == *byte,
+                _ => false,
+            }
+        })
+}
+
+/// Reads an entire file into a buffer that is never freed.
+///
+/// The buffer is followed by at least one NUL byte.
+pub fn read_to_leaked(path: &CStr) -> Result<&'static mut [u8], IoError> {
+    read_file_to_leaked(&mut File::open_cstr(path)?, path)
+}
+
+/// Reads an open file into a leaked buffer.
+fn read_file_to_leaked(file: &mut File, path: &CStr) -> Result<&'static mut [u8], IoError> {
+    let buf = sys::new_slice_leak(MAX_CONFIG_SIZE + 1)?;
+
+    let mut len = 0;
+    while len < MAX_CONFIG_SIZE {
+        match file.read(&mut buf[len..MAX_CONFIG_SIZE])? {
+            0 => return Ok(&mut buf[..len + 1]),
+            n => len += n,
+        }
+    }
+
+    log::warn!("{path:?} is larger than {MAX_CONFIG_SIZE} bytes");
+    Err(ErrorKind::FileTooLarge.into())
+}
+
+/// Displays bytes as a string for logging.
+pub struct AsStr<'a>(pub &'a [u8]);
+
+impl core::fmt::Debug for AsStr<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match core::str::from_utf8(self.0) {
+            Ok(s) => write!(f, "{s:?}"),
+            Err(_) => write!(f, "{:?}", self.0),
+        }
+    }
+}
@@ -4,27 +4,51 @@ use core::ffi::{CStr, c_void};
 use core::fmt;
 use core::mem;
 use core::ptr;
+use core::slice;
 
 pub use crate::arch::elf_types;
 use crate::arch::elf_types::{
-    header::{ET_DYN, Header},
-    program_header::{PF_R, PF_W, PF_X, PT_LOAD, ProgramHeader},
+    header::{ELFCLASS, ET_DYN, ET_EXEC, Header},
+    program_header::{
+        PF_R, PF_W, PF_X, PT_DYNAMIC, PT_GNU_PROPERTY, PT_INTERP, PT_LOAD, ProgramHeader,
+    },
 };
-use crate::arch::{EM_SELF, elf_jmp};
+use crate::arch::{self, EM_SELF, GNU_PROPERTY_FEATURE_1_AND, elf_jmp};
+use crate::trust::LoaderPolicy;
+use embedded_io::ReadExactError;
+use goblin::elf::header::{
+    EI_CLASS, EI_DATA, EI_OSABI, EI_VERSION, ELFDATA2LSB, ELFDATA2MSB, ELFMAG, ELFOSABI_GNU,
+    ELFOSABI_NONE, EV_CURRENT, SELFMAG,
+};
+use goblin::elf::note::{NT_GNU_PROPERTY_TYPE_0, Nhdr32};
 #[rustfmt::skip]
 use crate::sys::{
-    self, errno, Error as IoError, File, Read,
-    MAP_ANONYMOUS, MAP_FIXED, MAP_PRIVATE, MAP_FAILED,
+    self, errno, Error as IoError, ErrorKind, File, Read,
+    MAP_ANONYMOUS, MAP_FIXED, MAP_FIXED_NOREPLACE, MAP_PRIVATE,
     PROT_EXEC, PROT_NONE, PROT_READ, PROT_WRITE,
 };
 
+/// The byte order of the host.
+const ELFDATA_SELF: u8 = if cfg!(target_endian = "little") {
+    ELFDATA2LSB
+} else {
+    ELFDATA2MSB
+};
+
+/// The maximum size of `PT_GNU_PROPERTY` we read, like the kernel.
+const MAX_GNU_PROPERTY_SIZE: usize = 1024;
+
 pub struct ElfHandle {
     file: File,
+    path: &'static CStr,
     phs: ProgramHeaders,
     page_size: usize,
     entry_point_v: usize,
-    eh_map: *mut c_void,
-    eh_map_len: usize,
+
+    /// Whether the loader is non-relocatable (`ET_EXEC`).
+    fixed: bool,
+    ph_buf: *mut c_void,
+    ph_buf_len: usize,
 }
 
 pub struct ElfMapping {
@@ -43,11 +67,29 @@ pub struct ProgramHeadersIter<'ph> {
     index: usize,
 }
 
+/// An iterator over the entries of a note segment.
+pub struct NoteIter<'a> {
+    data: &'a [u8],
+    align: usize,
+}
+
+/// A note.
+pub struct Note<'a> {
+    pub n_type: u32,
+
+    /// The owner of the note, without the trailing NUL.
+    pub name: &'a [u8],
+    pub desc: &'a [u8],
+}
+
 struct DisplayPFlags<'ph>(&'ph ProgramHeader);
 
 struct LoadableSummary {
-    total_mapping_size: usize,
-    first_vaddr: usize,
+    min_vaddr: usize,
+    max_vaddr: usize,
+
+    /// The largest power-of-two `p_align` of the segments.
+    max_align: usize,
 }
 
 trait ProgramHeaderExt {
@@ -55,121 +97,139 @@ trait ProgramHeaderExt {
 }
 
 impl ElfHandle {
-    pub fn open(path: &CStr, page_size: usize) -> Result<Self, IoError> {
-        let mut file = File::open_cstr(path).or_else(|err| {
-            let path_bytes = path.to_bytes();
-            if err != errno::ENOENT || !path_bytes.ends_with(b"\n") {
-                return Err(err);
-            }
+    pub fn open(
+        path: &'static CStr,
+        page_size: usize,
+        policy: &LoaderPolicy,
+    ) -> Result<Self, IoError> {
+        let invalid = |kind: ErrorKind| IoError::new(kind).with_path(path);
+
+        let mut file = File::open_cstr(path)
+            .or_else(|err| {
+                let path_bytes = path.to_bytes();
+                if err != errno::ENOENT || !path_bytes.ends_with(b"\n") {
+                    return Err(err);
+                }
 
-            // ${stdenv.cc}/nix-support/dynamic-linker contains trailing newline
-            let truncated = &path_bytes[..path_bytes.len() - 1];
-            File::open(truncated)
-        })?;
+                // ${stdenv.cc}/nix-support/dynamic-linker contains trailing newline
+                let truncated = &path_bytes[..path_bytes.len() - 1];
+                File::open(truncated)
+            })
+            .map_err(|e| e.with_path(path))?;
+
+        policy.check(path, &file)?;
 
-        // TODO: Better error abstractions
         let mut buf = [0u8; mem::size_of::<Header>()];
-        file.read_exact(&mut buf).map_err(|_| {
-            log::error!("File too small");
-            IoError::Unknown
+        read_exact(&mut file, &mut buf, path).map_err(|e| {
+            // Wrapper scripts can be shorter than an ELF header
+            if buf.starts_with(b"#!") {
+                invalid(ErrorKind::Script)
+            } else {
+                e
+            }
         })?;
 
         let header = Header::from_bytes(&buf);
-        if &header.e_ident[..4] != b"\x7fELF".as_slice() {
-            log::error!("{path:?} is not an ELF");
-            return Err(IoError::Unknown);
+        validate_header(header).map_err(invalid)?;
+
+        // Skip to the program headers
+        let mut pos = mem::size_of::<Header>();
+        let phoff =
+            usize::try_from(header.e_phoff).map_err(|_| invalid(ErrorKind::BadProgramHeaders))?;
+        if phoff < pos {
+            return Err(invalid(ErrorKind::BadProgramHeaders));
         }
-
-        if header.e_machine != EM_SELF {
-            log::error!(
-                "{:?} is for the wrong architecture (expected 0x{:x}, got 0x{:x})",
-                path,
-                EM_SELF,
-                header.e_machine
-            );
-            return Err(IoError::Unknown);
-        }
-
-        if header.e_type != ET_DYN {
-            log::error!("{path:?} is not a dynamic library");
-            return Err(IoError::Unknown);
+        while pos < phoff {
+            let mut skip = [0u8; 256];
+            let len = core::cmp::min(skip.len(), phoff - pos);
+            read_exact(&mut file, &mut skip[..len], path)?;
+            pos += len;
         }
 
         let phsize = header.e_phentsize as usize * header.e_phnum as usize;
-        if phsize == 0 || phsize > 65536 {
-            log::error!("{path:?} has incorrect program header size {phsize}");
-            return Err(IoError::Unknown);
-        }
-
-        let eh_map_len = mem::size_of::<Header>() + phsize;
-        let eh_map = unsafe {
-            sys::mmap(
-                ptr::null_mut(),
-                eh_map_len,
-                PROT_READ,
-                MAP_PRIVATE,
-                file.as_raw_fd(),
-                0,
-            )
-        };
-
-        if eh_map == MAP_FAILED {
-            log::error!("Couldn't map headers of {:?} ({})", path, sys::errno());
-            return Err(IoError::Unknown);
-        }
-
-        let phdr = unsafe { eh_map.add(mem::size_of::<Header>()) };
+        let ph_buf = sys::new_slice_leak(phsize).map_err(|e| e.with_path(path))?;
+        read_exact(&mut file, ph_buf, path)?;
 
         let phs = ProgramHeaders {
-            base: phdr.cast(),
+            base: ph_buf.as_ptr().cast(),
             entry_size: header.e_phentsize as usize,
             num_entries: header.e_phnum as usize,
         };
 
-        Ok(Self {
+        let ph_buf = ph_buf.as_mut_ptr().cast();
+        let handle = Self {
             file,
+            path,
             phs,
             page_size,
             entry_point_v: header.e_entry as usize,
-            eh_map,
-            eh_map_len,
-        })
+            fixed: header.e_type == ET_EXEC,
+            ph_buf,
+            ph_buf_len: phsize,
+        };
+        handle.classify().map_err(invalid)?;
+
+        Ok(handle)
+    }
+
+    /// Checks that the program headers describe a loader.
+    ///
+    /// People regularly point `NIX_LD` at `libc.so.6` or some other
+    /// library, so we try to say what the file is instead.
+    fn classify(&self) -> Result<(), ErrorKind> {
+        let mut has_load = false;
+        let mut has_dynamic = false;
+
+        for ph in self.phs.iter() {
+            match ph.p_type {
+                PT_LOAD => has_load = true,
+                PT_DYNAMIC => has_dynamic = true,
+                PT_INTERP => return Err(ErrorKind::HasInterp),
+                _ => {}
+            }
+        }
+
+        if !has_load {
+            return Err(ErrorKind::NoLoadableSegments);
+        }
+
+        if !has_dynamic {
+            return Err(ErrorKind::NotDynamic);
+        }
+
+        if self.entry_point_v == 0 {
+            return Err(ErrorKind::NoEntryPoint);
+        }
+
+        Ok(())
     }
 
-    pub fn map(self) -> Result<ElfMapping, ()> {
+    pub fn map(self) -> Result<ElfMapping, IoError> {
         let summary = if let Some(summary) = self.phs.summarize_loadable() {
             summary
         } else {
-            log::error!("No program headers found");
-            return Err(());
+            return Err(IoError::new(ErrorKind::NoLoadableSegments).with_path(self.path));
         };
 
-        // For now, we assume the loader is relocatable and let
-        // the kernel decide the load addr.
-        let load_addr = unsafe {
-            sys::mmap(
-                ptr::null_mut(), // TODO: Maybe the ELF isn't relocatable
-                self.page_align(summary.total_mapping_size),
-                PROT_NONE,
-                MAP_PRIVATE | MAP_ANONYMOUS,
-                -1,
-                0,
-            )
-        };
-        if load_addr == MAP_FAILED {
-            log::error!("Failed to reserve");
-            return Err(());
-        }
+        // The kernel never sees our loader, so we apply its
+        // properties ourselves
+        let features = self.gnu_feature_1()?;
+        let exec_prot = supported_prot(arch::feature_1_prot(features));
 
-        // The first section's code starts at
-        //
-        //     load_addr + page_offset(ph.p_vaddr)
-        let load_bias = (load_addr as usize).wrapping_sub(self.page_start(summary.first_vaddr));
+        let load_bias = if self.fixed {
+            self.reserve_fixed(&summary)?;
+            0
+        } else {
+            self.reserve_relocatable(&summary)?
+        };
         let entry_point = (load_bias + self.entry_point_v) as *const c_void;
 
-        log::debug!("   Total Size: 0x{:x}", summary.total_mapping_size);
-        log::debug!("    Load Addr: {load_addr:x?}");
-        log::debug!("  First Vaddr: 0x{:x?}", summary.first_vaddr);
+        log::debug!(
+            "   Total Size: 0x{:x}",
+            summary.max_vaddr - summary.min_vaddr
+        );
+        log::debug!("    Min Vaddr: 0x{:x?}", summary.min_vaddr);
+        log::debug!("    Alignment: 0x{:x}", summary.max_align);
         log::debug!("    Load Bias: 0x{load_bias:x?}");
         log::debug!("  Entry Point: 0x{entry_point:x?}");
         log::debug!("    Page Size: {}", self.page_size);
@@ -178,11 +238,24 @@ impl ElfHandle {
             "GDB: add-symbol-file /path/to/ld.so.symbols 0x{load_bias:x}"
         );
 
+        let mut prev_end = None;
+        let mut read_into_memory = false;
         for ph in self.phs.iter() {
             if ph.p_type != PT_LOAD || ph.p_memsz == 0 {
                 continue;
             }
 
+            // Like the kernel, release the holes between segments
+            // instead of leaving them reserved
+            let seg_start = self.page_start(load_bias + ph.p_vaddr as usize);
+            if let Some(prev_end) = prev_end
+                && seg_start > prev_end
+            {
+                log::trace!("munmap [0x{prev_end:x}-0x{seg_start:x}] (gap)");
+                let _ = unsafe { sys::munmap(prev_end as *mut c_void, seg_start - prev_end) };
+            }
+            prev_end = Some(self.page_align(load_bias + (ph.p_vaddr + ph.p_memsz) as usize));
+
             let memsz = ph.p_memsz as usize;
             let filesz = ph.p_filesz as usize;
             let vaddr = ph.p_vaddr as usize;
@@ -190,7 +263,10 @@ impl ElfHandle {
             let fend = vaddr + filesz;
             let offset = ph.p_offset as usize;
 
-            let prot = ph.prot_flags();
+            let mut prot = ph.prot_flags();
+            if prot & PROT_EXEC != 0 {
+                prot |= exec_prot;
+            }
 
             let seg_page_start = self.page_start(load_bias + vaddr);
             let total_map_size = self.page_align(vend) - self.page_start(vaddr);
@@ -204,8 +280,9 @@ impl ElfHandle {
                 //     page_offset(ph.p_vaddr) == page_offset(ph.p_offset)
                 //
                 // We do the following mmap for the file-backed portion:
+                let page_offset = self.page_start(offset);
                 let mapping = unsafe {
-                    let offset = self.page_start(offset);
+                    let offset = page_offset;
                     let size = file_map_size;
 
                     log::trace!(
@@ -221,13 +298,27 @@ impl ElfHandle {
                         prot,
                         MAP_PRIVATE | MAP_FIXED,
                         self.file.as_raw_fd(),
-                        offset.try_into().unwrap(),
+                        offset,
                     )
                 };
 
-                if mapping == MAP_FAILED {
-                    log::error!("Failed to map segment 0x{:x} ({})", vaddr, sys::errno());
-                    return Err(());
+                if let Err(err) = mapping {
+                    let err = err.with_path(self.path);
+
+                    // The file system may be mounted noexec
+                    let denied = err == errno::EPERM || err == errno::EACCES;
+                    if !denied || prot & PROT_EXEC == 0 {
+                        log::error!("Failed to map segment 0x{vaddr:x}");
+                        return Err(err);
+                    }
+
+                    if !read_into_memory {
+                        log::info!("Cannot map the loader ({err}), reading it into memory instead");
+                        read_into_memory = true;
+                    }
+
+                    let len = core::cmp::min(fend, vend) - self.page_start(vaddr);
+                    self.read_segment(seg_page_start, file_map_size, page_offset, len, prot)?;
                 }
             }
 
@@ -238,12 +329,12 @@ impl ElfHandle {
                 let zero_end = self.page_align(zero_addr);
                 if zero_end > zero_addr {
                     unsafe {
-                        sys::memset(zero_addr as *mut c_void, 0, zero_end - zero_addr);
+                        ptr::write_bytes(zero_addr as *mut u8, 0, zero_end - zero_addr);
                     }
                 }
 
                 if file_map_size < total_map_size {
-                    let mapping = unsafe {
+                    unsafe {
                         let addr = seg_page_start + file_map_size;
                         let size = total_map_size - file_map_size;
                         log::trace!(
@@ -261,12 +352,11 @@ impl ElfHandle {
                             -1,
                             0,
                         )
-                    };
-
-                    if mapping == MAP_FAILED {
-                        log::error!("Failed to map anonymous portion for segment 0x{vaddr:x}");
-                        return Err(());
                     }
+                    .map_err(|err| {
+                        log::error!("Failed to map anonymous portion for segment 0x{vaddr:x}");
+                        err.with_path(self.path)
+                    })?;
                 }
             }
         }
@@ -277,6 +367,173 @@ impl ElfHandle {
         })
     }
 
+    /// Reads `len` bytes of a segment at `offset` into anonymous memory
+    /// at `addr`, instead of mapping the file.
+    fn read_segment(
+        &self,
+        addr: usize,
+        size: usize,
+        offset: usize,
+        len: usize,
+        prot: u32,
+    ) -> Result<(), IoError> {
+        log::trace!(
+            "read [0x{addr:x}-0x{end:x}] (offset=0x{offset:x})",
+            end = addr + len
+        );
+
+        let mapping = unsafe {
+            sys::mmap(
+                addr as *mut c_void,
+                size,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_FIXED | MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        }
+        .map_err(|e| e.with_path(self.path))?;
+
+        let buf = unsafe { slice::from_raw_parts_mut(mapping.cast(), len) };
+        self.file
+            .read_exact_at(buf, offset)
+            .map_err(|e| e.with_path(self.path))?;
+
+        unsafe { sys::mprotect(mapping, size, prot) }.map_err(|e| e.with_path(self.path))?;
+
+        Ok(())
+    }
+
+    /// Returns the `GNU_PROPERTY_*_FEATURE_1_AND` bits of the loader.
+    fn gnu_feature_1(&self) -> Result<u32, IoError> {
+        let malformed = || IoError::new(ErrorKind::BadGnuProperty).with_path(self.path);
+
+        let Some(ph) = self.phs.iter().find(|ph| ph.p_type == PT_GNU_PROPERTY) else {
+            return Ok(0);
+        };
+
+        let mut buf = [0u8; MAX_GNU_PROPERTY_SIZE];
+        let size = ph.p_filesz as usize;
+        if size > buf.len() {
+            return Err(malformed());
+        }
+        let buf = &mut buf[..size];
+        self.file
+            .read_exact_at(buf, ph.p_offset as usize)
+            .map_err(|e| e.with_path(self.path))?;
+
+        let mut features = 0;
+        for note in NoteIter::new(buf, ph.p_align as usize) {
+            if note.name != b"GNU" || note.n_type != NT_GNU_PROPERTY_TYPE_0 {
+                continue;
+            }
+
+            // An array of pr_type, pr_datasz and data padded to
+            // the word size
+            let mut desc = note.desc;
+            while !desc.is_empty() {
+                if desc.len() < 8 {
+                    return Err(malformed());
+                }
+                let pr_type = u32::from_ne_bytes(desc[0..4].try_into().unwrap());
+                let pr_datasz = u32::from_ne_bytes(desc[4..8].try_into().unwrap()) as usize;
+                let data = 8usize
+                    .checked_add(pr_datasz)
+                    .and_then(|end| desc.get(8..end))
+                    .ok_or_else(malformed)?;
+
+                if Some(pr_type) == GNU_PROPERTY_FEATURE_1_AND {
+                    let data = data.try_into().map_err(|_| malformed())?;
+                    features = u32::from_ne_bytes(data);
+                    log::debug!("GNU Features: 0x{features:x}");
+                }
+
+                let next = align_up(8 + pr_datasz, mem::size_of::<usize>());
+                desc = desc.get(next..).unwrap_or_default();
+            }
+        }
+
+        Ok(features)
+    }
+
+    /// Reserves an address range for a relocatable loader and returns
+    /// the load bias.
+    ///
+    /// Like the kernel, the load bias is aligned to the largest
+    /// `p_align` so that segments end up aligned in memory.
+    fn reserve_relocatable(&self, summary: &LoadableSummary) -> Result<usize, IoError> {
+        let align = core::cmp::max(summary.max_align, self.page_size);
+        let start = self.page_start(summary.min_vaddr);
+        let size = self.page_align(summary.max_vaddr) - start;
+        let reserve_size = size
+            .checked_add(align - self.page_size)
+            .ok_or_else(|| IoError::new(ErrorKind::BadProgramHeaders).with_path(self.path))?;
+
+        let addr = unsafe {
+            sys::mmap(
+                ptr::null_mut(),
+                reserve_size,
+                PROT_NONE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        }
+        .map_err(|e| e.with_op("reserve").with_path(self.path))?;
+
+        // Find the first address that is congruent to the first
+        // segment modulo the alignment, and trim the excess
+        let addr = addr as usize;
+        let load_addr = addr + (start.wrapping_sub(addr) & (align - 1));
+        unsafe {
+            if load_addr > addr {
+                let _ = sys::munmap(addr as *mut c_void, load_addr - addr);
+            }
+            let reserve_end = addr + reserve_size;
+            let load_end = load_addr + size;
+            if reserve_end > load_end {
+                let _ = sys::munmap(load_end as *mut c_void, reserve_end - load_end);
+            }
+        }
+
+        Ok(load_addr.wrapping_sub(start))
+    }
+
+    /// Reserves the exact address range of a non-relocatable loader.
+    ///
+    /// We never replace existing mappings, since those belong to us
+    /// or to the program.
+    fn reserve_fixed(&self, summary: &LoadableSummary) -> Result<(), IoError> {
+        let start = self.page_start(summary.min_vaddr);
+        let end = self.page_align(summary.max_vaddr);
+        let in_use = || IoError::new(ErrorKind::AddressInUse { start, end }).with_path(self.path);
+
+        let addr = unsafe {
+            sys::mmap(
+                start as *mut c_void,
+                end - start,
+                PROT_NONE,
+                MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED_NOREPLACE,
+                -1,
+                0,
+            )
+        };
+        let addr = match addr {
+            Ok(addr) => addr,
+            Err(err) if err == errno::EEXIST => return Err(in_use()),
+            Err(err) => return Err(err.with_op("reserve").with_path(self.path)),
+        };
+
+        // Kernels before 4.17 treat the address as a hint
+        if addr as usize != start {
+            let _ = unsafe { sys::munmap(addr, end - start) };
+            return Err(in_use());
+        }
+
+        log::debug!("Reserved 0x{start:x}-0x{end:x} for the fixed-address loader");
+        Ok(())
+    }
+
     #[inline(always)]
     fn page_align(&self, v: usize) -> usize {
         (v + self.page_size - 1) & !(self.page_size - 1)
@@ -290,10 +547,101 @@ impl ElfHandle {
 
 impl Drop for ElfHandle {
     fn drop(&mut self) {
-        unsafe {
-            sys::munmap(self.eh_map, self.eh_map_len);
+        let _ = unsafe { sys::munmap(self.ph_buf, self.ph_buf_len) };
+    }
+}
+
+/// Returns the subset of extra protection flags the kernel accepts.
+///
+/// For example, `PROT_BTI` is rejected on CPUs without BTI.
+fn supported_prot(prot: u32) -> u32 {
+    if prot == 0 {
+        return 0;
+    }
+
+    let probe = unsafe {
+        sys::mmap(
+            ptr::null_mut(),
+            1,
+            PROT_READ | PROT_EXEC | prot,
+            MAP_PRIVATE | MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+    };
+    let Ok(probe) = probe else {
+        log::debug!("Protection flags 0x{prot:x} are not supported");
+        return 0;
+    };
+
+    let _ = unsafe { sys::munmap(probe, 1) };
+    prot
+}
+
+/// Checks the ELF header of a loader.
+fn validate_header(header: &Header) -> Result<(), ErrorKind> {
+    if header.e_ident[..SELFMAG] != ELFMAG[..] {
+        if header.e_ident.starts_with(b"#!") {
+            return Err(ErrorKind::Script);
         }
+        return Err(ErrorKind::NotElf);
+    }
+
+    if header.e_ident[EI_CLASS] != ELFCLASS {
+        return Err(ErrorKind::WrongClass {
+            expected: ELFCLASS,
+            found: header.e_ident[EI_CLASS],
+        });
+    }
+
+    if header.e_ident[EI_DATA] != ELFDATA_SELF {
+        return Err(ErrorKind::WrongEndian {
+            expected: ELFDATA_SELF,
+            found: header.e_ident[EI_DATA],
+        });
+    }
+
+    if header.e_ident[EI_VERSION] != EV_CURRENT {
+        return Err(ErrorKind::BadVersion(header.e_ident[EI_VERSION].into()));
+    }
+
+    if header.e_version != EV_CURRENT.into() {
+        return Err(ErrorKind::BadVersion(header.e_version));
+    }
+
+    let osabi = header.e_ident[EI_OSABI];
+    if osabi != ELFOSABI_NONE && osabi != ELFOSABI_GNU {
+        return Err(ErrorKind::BadOsAbi(osabi));
+    }
+
+    if header.e_machine != EM_SELF {
+        return Err(ErrorKind::WrongArch {
+            expected: EM_SELF,
+            found: header.e_machine,
+        });
     }
+
+    if header.e_type != ET_DYN && header.e_type != ET_EXEC {
+        return Err(ErrorKind::WrongType(header.e_type));
+    }
+
+    let phsize = header.e_phentsize as usize * header.e_phnum as usize;
+    if header.e_phentsize as usize != mem::size_of::<ProgramHeader>()
+        || phsize == 0
+        || phsize > 65536
+    {
+        log::error!("Incorrect program header size {phsize}");
+        return Err(ErrorKind::BadProgramHeaders);
+    }
+
+    Ok(())
+}
+
+fn read_exact(file: &mut File, buf: &mut [u8], path: &'static CStr) -> Result<(), IoError> {
+    file.read_exact(buf).map_err(|e| match e {
+        ReadExactError::UnexpectedEof => IoError::new(ErrorKind::UnexpectedEof).with_path(path),
+        ReadExactError::Other(e) => e.with_path(path),
+    })
 }
 
 impl ElfMapping {
@@ -322,7 +670,11 @@ impl ProgramHeaders {
         }
     }
 
-    pub fn iter(&self) -> ProgramHeadersIter {
+    pub fn as_ptr(&self) -> *const ProgramHeader {
+        self.base
+    }
+
+    pub fn iter(&self) -> ProgramHeadersIter<'_> {
         ProgramHeadersIter {
             headers: self,
             index: 0,
@@ -330,17 +682,22 @@ impl ProgramHeaders {
     }
 
     fn summarize_loadable(&self) -> Option<LoadableSummary> {
-        let mut first_vaddr = None;
+        let mut found = false;
         let mut addr_min = usize::MAX;
         let mut addr_max = usize::MIN;
+        let mut max_align = 0;
 
         for ph in self.iter() {
             if ph.p_type != PT_LOAD || ph.p_memsz == 0 {
                 continue;
             }
 
-            if first_vaddr.is_none() {
-                first_vaddr = Some(ph.p_vaddr as usize);
+            found = true;
+
+            // Bogus alignments are ignored, like the kernel does
+            let align = ph.p_align as usize;
+            if align.is_power_of_two() && align > max_align {
+                max_align = align;
             }
 
             if addr_min > ph.p_vaddr as usize {
@@ -353,9 +710,10 @@ impl ProgramHeaders {
             }
         }
 
-        first_vaddr.map(|first_vaddr| LoadableSummary {
-            first_vaddr,
-            total_mapping_size: addr_max - addr_min,
+        found.then_some(LoadableSummary {
+            min_vaddr: addr_min,
+            max_vaddr: addr_max,
+            max_align,
         })
     }
 }
@@ -379,6 +737,51 @@ impl<'ph> Iterator for ProgramHeadersIter<'ph> {
     }
 }
 
+impl<'a> NoteIter<'a> {
+    /// Creates an iterator over the notes in a segment with the given
+    /// `p_align`.
+    pub fn new(data: &'a [u8], align: usize) -> Self {
+        // Notes are either 4- or 8-byte aligned
+        let align = if align == 8 { 8 } else { 4 };
+        Self { data, align }
+    }
+}
+
+impl<'a> Iterator for NoteIter<'a> {
+    type Item = Note<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header_len = mem::size_of::<Nhdr32>();
+        if self.data.len() < header_len {
+            return None;
+        }
+
+        let header: Nhdr32 = unsafe { ptr::read_unaligned(self.data.as_ptr().cast()) };
+        let namesz = header.n_namesz as usize;
+        let descsz = header.n_descsz as usize;
+
+        let desc_start = align_up(header_len.checked_add(namesz)?, self.align);
+        let desc_end = desc_start.checked_add(descsz)?;
+        if desc_end > self.data.len() {
+            self.data = &[];
+            return None;
+        }
+
+        let name = &self.data[header_len..header_len + namesz];
+        let name = name.strip_suffix(b"\0").unwrap_or(name);
+        let desc = &self.data[desc_start..desc_end];
+
+        let next = align_up(desc_end, self.align).min(self.data.len());
+        self.data = &self.data[next..];
+
+        Some(Note {
+            n_type: header.n_type,
+            name,
+            desc,
+        })
+    }
+}
+
 impl ProgramHeaderExt for ProgramHeader {
     #[inline(always)]
     fn prot_flags(&self) -> u32 {
@@ -389,6 +792,11 @@ impl ProgramHeaderExt for ProgramHeader {
     }
 }
 
+#[inline(always)]
+fn align_up(v: usize, align: usize) -> usize {
+    (v + align - 1) & !(align - 1)
+}
+
 impl fmt::Display for DisplayPFlags<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let p_flags = &self.0.p_flags;
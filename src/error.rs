@@ -0,0 +1,262 @@
+//! Errors.
+//!
+//! Errors carry the operation that failed and the path involved when
+//! known, so that reports like "loader not found" and "loader is for
+//! the wrong architecture" can be told apart.
+
+use core::ffi::CStr;
+use core::fmt;
+
+use goblin::elf::header::{ELFCLASS32, ELFCLASS64, ELFDATA2LSB, ELFDATA2MSB};
+use linux_raw_sys::errno;
+
+/// An error.
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    op: Option<&'static str>,
+    path: Option<&'static CStr>,
+}
+
+/// The kind of an error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A system call failed with an errno.
+    Os(u32),
+
+    /// A path doesn't fit into a buffer.
+    PathTooLong,
+
+    /// A file is larger than we are willing to read.
+    FileTooLarge,
+
+    /// A file ended before the data we need.
+    UnexpectedEof,
+
+    /// There is no space left for environment edits.
+    TooManyEnv,
+
+    /// A removed environment variable cannot be restored.
+    RemovedEnv,
+
+    /// The file is not an ELF.
+    NotElf,
+
+    /// The file is a script, like the wrappers in `bintools`.
+    Script,
+
+    /// The ELF has the wrong word size.
+    WrongClass { expected: u8, found: u8 },
+
+    /// The ELF has the wrong byte order.
+    WrongEndian { expected: u8, found: u8 },
+
+    /// The ELF has an unknown version.
+    BadVersion(u32),
+
+    /// The ELF is for another operating system.
+    BadOsAbi(u8),
+
+    /// The ELF is for another architecture.
+    WrongArch { expected: u16, found: u16 },
+
+    /// The ELF is neither an executable nor a shared object.
+    WrongType(u16),
+
+    /// The ELF has no dynamic section.
+    NotDynamic,
+
+    /// The ELF has its own interpreter, so it is a program.
+    HasInterp,
+
+    /// The ELF has no entry point, so it is a plain shared library.
+    NoEntryPoint,
+
+    /// The program headers are missing or malformed.
+    BadProgramHeaders,
+
+    /// There are no loadable segments.
+    NoLoadableSegments,
+
+    /// The `PT_GNU_PROPERTY` segment is malformed.
+    BadGnuProperty,
+
+    /// The address range of a non-relocatable ELF is already mapped.
+    AddressInUse { start: usize, end: usize },
+
+    /// The loader is not a regular file.
+    NotRegularFile,
+
+    /// The loader is not owned by root.
+    NotOwnedByRoot { uid: u32 },
+
+    /// The loader is writable by the group or others.
+    Writable { mode: u32 },
+
+    /// The loader is not under a trusted prefix.
+    UntrustedPath,
+}
+
+impl Error {
+    pub const fn new(kind: ErrorKind) -> Self {
+        Self {
+            kind,
+            op: None,
+            path: None,
+        }
+    }
+
+    /// Sets the operation that failed.
+    pub const fn with_op(mut self, op: &'static str) -> Self {
+        self.op = Some(op);
+        self
+    }
+
+    /// Sets the path the operation failed on.
+    pub const fn with_path(mut self, path: &'static CStr) -> Self {
+        self.path = Some(path);
+        self
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Self::new(kind)
+    }
+}
+
+impl PartialEq<u32> for Error {
+    fn eq(&self, other: &u32) -> bool {
+        self.kind == ErrorKind::Os(*other)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.op, self.path) {
+            (Some(op), Some(path)) => write!(f, "{op} {path:?}: ")?,
+            (Some(op), None) => write!(f, "{op}: ")?,
+            (None, Some(path)) => write!(f, "{path:?}: ")?,
+            (None, None) => {}
+        }
+        write!(f, "{}", self.kind)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Os(num) => match strerror(num) {
+                Some((name, message)) => write!(f, "{message} ({name})"),
+                None => write!(f, "Unknown error {num}"),
+            },
+            Self::PathTooLong => f.write_str("Path too long"),
+            Self::FileTooLarge => f.write_str("File too large"),
+            Self::UnexpectedEof => f.write_str("File is truncated"),
+            Self::TooManyEnv => f.write_str("Too many environment edits"),
+            Self::RemovedEnv => f.write_str("Removed environment variables cannot be restored"),
+            Self::NotElf => f.write_str("Not an ELF file"),
+            Self::Script => f.write_str("Is a script (maybe a compiler wrapper), not an ELF file"),
+            Self::WrongClass { expected, found } => write!(
+                f,
+                "ELF is {}, expected {}",
+                DisplayClass(found),
+                DisplayClass(expected)
+            ),
+            Self::WrongEndian { expected, found } => write!(
+                f,
+                "ELF is {}, expected {}",
+                DisplayData(found),
+                DisplayData(expected)
+            ),
+            Self::BadVersion(version) => write!(f, "ELF has unknown version {version}"),
+            Self::BadOsAbi(osabi) => write!(f, "ELF is for unsupported OS/ABI {osabi}"),
+            Self::WrongArch { expected, found } => write!(
+                f,
+                "ELF is for the wrong architecture (expected 0x{expected:x}, got 0x{found:x})"
+            ),
+            Self::WrongType(e_type) => write!(f, "ELF has unsupported type {e_type}"),
+            Self::NotDynamic => f.write_str("ELF has no dynamic section, so it is not a loader"),
+            Self::HasInterp => f.write_str(
+                "ELF requests its own interpreter, so it is a program (like libc.so.6), not a loader",
+            ),
+            Self::NoEntryPoint => f.write_str(
+                "ELF has no entry point, so it is a shared library, not a loader",
+            ),
+            Self::BadProgramHeaders => f.write_str("ELF has malformed program headers"),
+            Self::NoLoadableSegments => f.write_str("ELF has no loadable segments"),
+            Self::BadGnuProperty => f.write_str("ELF has a malformed GNU property note"),
+            Self::AddressInUse { start, end } => write!(
+                f,
+                "Address range 0x{start:x}-0x{end:x} needed by the non-relocatable ELF is in use"
+            ),
+            Self::NotRegularFile => f.write_str("Not a regular file"),
+            Self::NotOwnedByRoot { uid } => write!(f, "Owned by uid {uid} instead of root"),
+            Self::Writable { mode } => {
+                write!(f, "Writable by group or others (mode {mode:o})")
+            }
+            Self::UntrustedPath => f.write_str("Not under a trusted prefix"),
+        }
+    }
+}
+
+struct DisplayClass(u8);
+
+struct DisplayData(u8);
+
+impl fmt::Display for DisplayClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            ELFCLASS32 => f.write_str("32-bit"),
+            ELFCLASS64 => f.write_str("64-bit"),
+            class => write!(f, "of unknown class {class}"),
+        }
+    }
+}
+
+impl fmt::Display for DisplayData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            ELFDATA2LSB => f.write_str("little-endian"),
+            ELFDATA2MSB => f.write_str("big-endian"),
+            data => write!(f, "of unknown byte order {data}"),
+        }
+    }
+}
+
+/// Returns the name and description of an errno.
+pub fn strerror(num: u32) -> Option<(&'static str, &'static str)> {
+    Some(match num {
+        errno::EPERM => ("EPERM", "Operation not permitted"),
+        errno::ENOENT => ("ENOENT", "No such file or directory"),
+        errno::EINTR => ("EINTR", "Interrupted system call"),
+        errno::EIO => ("EIO", "Input/output error"),
+        errno::E2BIG => ("E2BIG", "Argument list too long"),
+        errno::ENOEXEC => ("ENOEXEC", "Exec format error"),
+        errno::EBADF => ("EBADF", "Bad file descriptor"),
+        errno::EAGAIN => ("EAGAIN", "Resource temporarily unavailable"),
+        errno::ENOMEM => ("ENOMEM", "Cannot allocate memory"),
+        errno::EACCES => ("EACCES", "Permission denied"),
+        errno::EFAULT => ("EFAULT", "Bad address"),
+        errno::EEXIST => ("EEXIST", "File exists"),
+        errno::ENODEV => ("ENODEV", "No such device"),
+        errno::ENOTDIR => ("ENOTDIR", "Not a directory"),
+        errno::EISDIR => ("EISDIR", "Is a directory"),
+        errno::EINVAL => ("EINVAL", "Invalid argument"),
+        errno::ENFILE => ("ENFILE", "Too many open files in system"),
+        errno::EMFILE => ("EMFILE", "Too many open files"),
+        errno::ETXTBSY => ("ETXTBSY", "Text file busy"),
+        errno::EFBIG => ("EFBIG", "File too large"),
+        errno::ENOSPC => ("ENOSPC", "No space left on device"),
+        errno::EROFS => ("EROFS", "Read-only file system"),
+        errno::ERANGE => ("ERANGE", "Numerical result out of range"),
+        errno::ENAMETOOLONG => ("ENAMETOOLONG", "File name too long"),
+        errno::ENOSYS => ("ENOSYS", "Function not implemented"),
+        errno::ELOOP => ("ELOOP", "Too many levels of symbolic links"),
+        errno::ENODATA => ("ENODATA", "No data available"),
+        errno::EOVERFLOW => ("EOVERFLOW", "Value too large for defined data type"),
+        errno::ELIBBAD => ("ELIBBAD", "Accessing a corrupted shared library"),
+        errno::EOPNOTSUPP => ("EOPNOTSUPP", "Operation not supported"),
+        _ => return None,
+    })
+}
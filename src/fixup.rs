@@ -16,6 +16,11 @@ use crate::elf::{
 };
 use crate::support::explode;
 
+// Not in goblin yet
+const DT_RELRSZ: u64 = 35;
+const DT_RELR: u64 = 36;
+const DT_RELRENT: u64 = 37;
+
 struct Dynamic {
     ptr: *const elf_types::dynamic::Dyn,
     load_offset: usize,
@@ -39,6 +44,9 @@ impl Dynamic {
         let mut rel_data = None;
         let mut rel_len = None;
 
+        let mut relr_data = None;
+        let mut relr_len = None;
+
         loop {
             let entry = unsafe { &*cur };
 
@@ -77,6 +85,21 @@ impl Dynamic {
                     }
                 }
 
+                // DT_RELR
+                DT_RELR => {
+                    relr_data =
+                        Some(entry.d_val.wrapping_add(self.load_offset as _) as *const usize);
+                }
+                DT_RELRSZ => {
+                    relr_len = Some(entry.d_val as usize / mem::size_of::<usize>());
+                }
+                DT_RELRENT => {
+                    let actual_size = entry.d_val as usize;
+                    if actual_size != mem::size_of::<usize>() {
+                        explode("DT_RELRENT has unsupported size");
+                    }
+                }
+
                 _ => {}
             }
 
@@ -114,14 +137,53 @@ impl Dynamic {
                 }
             }
         }
+
+        if let (Some(relr_data), Some(relr_len)) = (relr_data, relr_len) {
+            let relr = unsafe { slice::from_raw_parts(relr_data, relr_len) };
+            self.fixup_relr(relr);
+        }
+    }
+
+    /// Applies packed relative relocations.
+    ///
+    /// Each even entry is the address of a word to relocate. Each
+    /// odd entry is a bitmap of the next 63 (or 31) words after the
+    /// last address, with bit 0 being the marker.
+    fn fixup_relr(&self, relr: &[usize]) {
+        let relocate = |ptr: *mut usize| unsafe {
+            *ptr = self.load_offset.wrapping_add(*ptr);
+        };
+
+        let mut base: *mut usize = ptr::null_mut();
+        for &entry in relr {
+            if entry & 1 == 0 {
+                let ptr = self.load_offset.wrapping_add(entry) as *mut usize;
+                relocate(ptr);
+                base = ptr.wrapping_add(1);
+            } else {
+                if base.is_null() {
+                    explode("DT_RELR starts with a bitmap");
+                }
+
+                let mut bitmap = entry >> 1;
+                let mut ptr = base;
+                while bitmap != 0 {
+                    if bitmap & 1 != 0 {
+                        relocate(ptr);
+                    }
+                    bitmap >>= 1;
+                    ptr = ptr.wrapping_add(1);
+                }
+                base = base.wrapping_add(usize::BITS as usize - 1);
+            }
+        }
     }
 }
 
 pub unsafe fn fixup_relocs(envp: *const *const u8) {
     unsafe {
         // Reference: <https://gist.github.com/Amanieu/588e3f9d330019c5d39f3ce60e8e0aae>
-        let auxv = find_auxv(envp);
-        let auxv = AuxVec::from_raw(auxv);
+        let auxv = AuxVec::from_envp(envp);
 
         let at_base = auxv.at_base.as_ref().map_or_else(ptr::null, |v| v.value());
         let (load_offset, phs) = if at_base.is_null() {
@@ -166,13 +228,3 @@ pub unsafe fn fixup_relocs(envp: *const *const u8) {
         dynamic.fixup();
     }
 }
-
-unsafe fn find_auxv(envp: *const *const u8) -> *const usize {
-    unsafe {
-        let mut cur = envp;
-        while !(*cur).is_null() {
-            cur = cur.add(1);
-        }
-        cur.add(1) as *const usize
-    }
-}
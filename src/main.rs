@@ -1,21 +1,27 @@
-#![feature(lang_items)]
+#![cfg_attr(not(test), feature(lang_items))]
 #![no_std]
 #![no_main]
 #![allow(internal_features)]
 #![allow(static_mut_refs)]
 
+mod allowlist;
 mod arch;
 mod args;
 mod auxv;
+mod config;
 mod const_concat;
 mod elf;
+mod error;
 mod fixup;
+mod sha256;
 mod support;
 mod sys;
+mod target;
+mod trust;
 
 use core::ffi::{CStr, c_void};
+use core::fmt;
 use core::mem::MaybeUninit;
-use core::ptr;
 
 use crate::const_concat::concat_slices;
 
@@ -23,8 +29,12 @@ use arch::{
     NIX_LD_LIBRARY_PATH_SYSTEM_ENV, NIX_LD_LIBRARY_PATH_SYSTEM_ENV_BYTES, NIX_LD_SYSTEM_ENV,
     NIX_LD_SYSTEM_ENV_BYTES,
 };
-use args::{Args, EnvEdit, VarHandle};
+use args::{Args, EnvEdit, EnvSlot, MAX_REMOVED_ENV, VarHandle};
+use config::{AsStr, Config, MAX_PROFILE_ENV, Settings};
+use heapless::Vec as ArrayVec;
 use support::StackSpace;
+use target::Target;
+use trust::LoaderPolicy;
 
 static mut ARGS: MaybeUninit<Args> = MaybeUninit::uninit();
 static mut STACK: MaybeUninit<StackSpace> = MaybeUninit::uninit();
@@ -42,17 +52,28 @@ const DEFAULT_NIX_LD: &CStr = unsafe {
 const DEFAULT_NIX_LD_LIBRARY_PATH: &[u8] = b"/run/current-system/sw/share/nix-ld/lib";
 const EMPTY_LD_LIBRARY_PATH_ENV: &CStr = c"LD_LIBRARY_PATH=";
 
+/// The maximum number of directories in LD_LIBRARY_PATH.
+const MAX_LIBRARY_PATH_COMPONENTS: usize = 256;
+
 #[derive(Default)]
 struct Context {
     nix_ld: Option<VarHandle>,
     nix_ld_library_path: Option<VarHandle>,
     ld_library_path: Option<VarHandle>,
+
+    /// Existing variables overridden for the program, in the order
+    /// of `Settings::env`.
+    program_env: [Option<VarHandle>; MAX_PROFILE_ENV],
+
+    /// Earlier copies of the overridden variables, which would shadow
+    /// our values in getenv().
+    shadowed_env: ArrayVec<VarHandle, MAX_REMOVED_ENV>,
 }
 
-#[unsafe(no_mangle)]
-unsafe extern "C" fn main(argc: usize, argv: *const *const u8, envp: *const *const u8) -> ! {
+unsafe fn main(argc: usize, argv: *const *const u8, envp: *const *const u8) -> ! {
     unsafe {
         fixup::fixup_relocs(envp);
+        support::init_stack_guard(envp);
 
         ARGS.write(Args::new(argc, argv, envp));
         let stack = STACK.assume_init_mut().bottom();
@@ -69,18 +90,67 @@ extern "C" fn real_main() -> ! {
         .map(|_| log::set_max_level(log::LevelFilter::Warn))
         .unwrap();
 
+    // Like glibc, we must not let the caller of a setuid/setgid program
+    // choose the loader or libraries it runs with
+    let secure = args.auxv().is_secure();
+
+    let nix_ld_log = args.get_env(b"NIX_LD_LOG").filter(|_| !secure);
+    if let Some(log_level) = nix_ld_log {
+        set_log_level(log_level);
+    }
+
+    // Config files have lower precedence than the environment
+    let config = load_config(args, secure);
+    if nix_ld_log.is_none()
+        && let Some(log_level) = config.settings.nix_ld_log
+    {
+        set_log_level(log_level);
+    }
+
+    // When we are executed directly, there is no program to match
+    let is_loader = args
+        .auxv()
+        .at_base
+        .as_ref()
+        .is_some_and(|base| !base.value().is_null());
+    let target = is_loader.then(|| {
+        let target = Target::from_args(args);
+        log::debug!("Target: {target:?}");
+        target
+    });
+
+    let mut policy = LoaderPolicy::new();
+    for prefix in &config.policy.trusted_loader_prefixes {
+        log::info!("Trusting loaders under {:?}", AsStr(prefix));
+        policy.add_prefix(prefix);
+    }
+
+    // Settings for the program have higher precedence than the environment
+    let program_settings = target
+        .as_ref()
+        .map(|target| load_program_settings(target, &config, &policy, secure))
+        .unwrap_or_default();
+    if let Some(log_level) = program_settings.nix_ld_log {
+        set_log_level(log_level);
+    }
+
+    let mut ignored_nix_ld_vars = false;
     for env in args.iter_env().unwrap() {
-        match env.name() {
-            b"NIX_LD_LOG" => {
-                if let Ok(log_level) = env.value_cstr().to_str() {
-                    if let Ok(level) = log_level.parse::<log::LevelFilter>() {
-                        log::set_max_level(level);
-                    } else {
-                        log::warn!("Unknown log level {log_level}");
-                    }
-                }
-            }
+        let is_nix_ld_var = matches!(
+            env.name(),
+            NIX_LD_SYSTEM_ENV_BYTES
+                | b"NIX_LD"
+                | NIX_LD_LIBRARY_PATH_SYSTEM_ENV_BYTES
+                | b"NIX_LD_LIBRARY_PATH"
+                | b"NIX_LD_LOG"
+        );
+        if secure && is_nix_ld_var {
+            log::info!("Ignoring {:?} in secure-execution mode", AsStr(env.name()));
+            ignored_nix_ld_vars = true;
+            continue;
+        }
 
+        match env.name() {
             // The system-specific variants (e.g., NIX_LD_x86_64_linux) always
             // take precedence. Currently, NIX_LD_LIBRARY_PATH_{system} clobbers
             // the generic one, and we should revisit this decision (maybe
@@ -100,25 +170,75 @@ extern "C" fn real_main() -> ! {
             b"LD_LIBRARY_PATH" => {
                 ctx.ld_library_path = Some(env);
             }
-            _ => {}
+            name => {
+                if let Some(i) = program_settings
+                    .env
+                    .iter()
+                    .position(|var| var.name.as_bytes() == name)
+                    && let Some(shadowed) = ctx.program_env[i].replace(env)
+                    && ctx.shadowed_env.push(shadowed).is_err()
+                {
+                    log::warn!(
+                        "Too many duplicate {} variables",
+                        program_settings.env[i].name
+                    );
+                }
+            }
         }
     }
 
+    if ignored_nix_ld_vars {
+        log::warn!("Ignoring NIX_LD* environment variables in secure-execution mode");
+    }
+
+    // The library path used in place of an unset NIX_LD_LIBRARY_PATH
+    let default_library_path = if let Some(path) = config.settings.nix_ld_library_path {
+        log::info!("NIX_LD_LIBRARY_PATH from config is {path:?}");
+        path.to_bytes()
+    } else {
+        DEFAULT_NIX_LD_LIBRARY_PATH
+    };
+
+    // The library path used in place of NIX_LD_LIBRARY_PATH even if set
+    let program_library_path = program_settings.nix_ld_library_path.map(|path| {
+        log::info!("NIX_LD_LIBRARY_PATH for the program is {path:?}");
+        path.to_bytes()
+    });
+
+    // Library paths declared by the program itself
+    let note_library_paths: &[&[u8]] = target.as_ref().map_or(&[], |t| &t.library_paths);
+    if !note_library_paths.is_empty() {
+        log::info!("Prepending library paths from the program notes to NIX_LD_LIBRARY_PATH");
+    }
+
+    // Whether to drop library directories that don't exist
+    let skip_missing_dirs = program_settings
+        .skip_missing_dirs
+        .or(config.settings.skip_missing_dirs)
+        .unwrap_or(false);
+
     // Deal with NIX_LD
-    let nix_ld = match &mut ctx.nix_ld {
-        None => {
-            log::info!("NIX_LD is not set - Falling back to default");
-            DEFAULT_NIX_LD
-        }
-        Some(nix_ld) if nix_ld.value().is_empty() => {
-            log::info!("NIX_LD is empty - Falling back to default");
-            DEFAULT_NIX_LD
+    let program_nix_ld = program_settings.nix_ld.filter(|nix_ld| !nix_ld.is_empty());
+    let (nix_ld, nix_ld_source) = match (program_nix_ld, &mut ctx.nix_ld) {
+        (Some(nix_ld), _) => {
+            log::info!("NIX_LD for the program is {nix_ld:?}");
+            (nix_ld, LoaderSource::Program)
         }
-        Some(nix_ld) => {
+        (None, Some(nix_ld)) if !nix_ld.value().is_empty() => {
             let cstr = nix_ld.value_cstr();
             log::info!("NIX_LD is set to {cstr:?}");
-            cstr
+            (cstr, LoaderSource::Env(nix_ld.name()))
         }
+        (None, _) => match config.settings.nix_ld {
+            Some(nix_ld) if !nix_ld.is_empty() => {
+                log::info!("NIX_LD from config is {nix_ld:?}");
+                (nix_ld, LoaderSource::Config)
+            }
+            _ => {
+                log::info!("NIX_LD is not set - Falling back to default");
+                (DEFAULT_NIX_LD, LoaderSource::Default)
+            }
+        },
     };
 
     // Deal with {NIX_,}LD_LIBRARY_PATH
@@ -127,42 +247,41 @@ extern "C" fn real_main() -> ! {
         //
         // Basically LD_LIBRARY_PATH=$LD_LIBRARY_PATH:$NIX_LD_LIBRARY_PATH
         let head = ld_library_path.value();
-        let tail = if let Some(nix_ld_library_path) = &ctx.nix_ld_library_path {
+        let tail = if let Some(path) = program_library_path {
+            log::info!("Appending NIX_LD_LIBRARY_PATH for the program to LD_LIBRARY_PATH");
+            path
+        } else if let Some(nix_ld_library_path) = &ctx.nix_ld_library_path {
             log::info!("Appending NIX_LD_LIBRARY_PATH to LD_LIBRARY_PATH");
             nix_ld_library_path.value()
         } else {
             log::info!("Appending default NIX_LD_LIBRARY_PATH to LD_LIBRARY_PATH");
-            DEFAULT_NIX_LD_LIBRARY_PATH
+            default_library_path
         };
 
-        let sep: &[u8] = if head.is_empty() || head.last() == Some(&b':') {
-            &[]
+        let path = JoinedPath::new(&[head], note_library_paths, &[tail], skip_missing_dirs);
+        ld_library_path.edit(None, path.len(), |_, new| path.write(new))
+    } else if let Some(nix_ld_library_path) = ctx.nix_ld_library_path.take() {
+        // NIX_LD_LIBRARY_PATH must always exist for impure child processes to work
+        let tail = if let Some(path) = program_library_path {
+            log::info!("Replacing NIX_LD_LIBRARY_PATH with LD_LIBRARY_PATH for the program");
+            path
         } else {
-            b":"
+            log::info!("Renaming NIX_LD_LIBRARY_PATH to LD_LIBRARY_PATH");
+            nix_ld_library_path.value()
         };
-        let new_len = head.len() + tail.len() + sep.len();
 
-        ld_library_path.edit(None, new_len, |head, new| {
-            new[..head.len()].copy_from_slice(head);
-            new[head.len()..head.len() + sep.len()].copy_from_slice(sep);
-            new[head.len() + sep.len()..].copy_from_slice(tail);
+        let path = JoinedPath::new(&[], note_library_paths, &[tail], skip_missing_dirs);
+        nix_ld_library_path.edit(Some("LD_LIBRARY_PATH"), path.len(), |_, new| {
+            path.write(new)
         })
-    } else if let Some(nix_ld_library_path) = ctx.nix_ld_library_path.take() {
-        log::info!("Renaming NIX_LD_LIBRARY_PATH to LD_LIBRARY_PATH");
-
-        // NIX_LD_LIBRARY_PATH must always exist for impure child processes to work
-        nix_ld_library_path.rename("LD_LIBRARY_PATH")
     } else {
         log::info!("Neither LD_LIBRARY_PATH or NIX_LD_LIBRARY_PATH exist - Setting default");
 
-        args.add_env(
-            "LD_LIBRARY_PATH",
-            DEFAULT_NIX_LD_LIBRARY_PATH.len(),
-            |buf| {
-                buf.copy_from_slice(DEFAULT_NIX_LD_LIBRARY_PATH);
-            },
-        )
-        .unwrap();
+        let tail = program_library_path.unwrap_or(default_library_path);
+        let path = JoinedPath::new(&[], note_library_paths, &[tail], skip_missing_dirs);
+        let index = args
+            .add_env("LD_LIBRARY_PATH", path.len(), |buf| path.write(buf))
+            .unwrap();
 
         // If the entry trampoline is available on the platform, LD_LIBRARY_PATH
         // will be replaced with an empty LD_LIBRARY_PATH when ld.so launches
@@ -171,11 +290,35 @@ extern "C" fn real_main() -> ! {
         // We cannot replace it with NIX_LD_LIBRARY_PATH as it would take
         // precedence over config files.
         EnvEdit {
-            entry: ptr::null(),
+            slot: EnvSlot::Extra(index),
             old_string: EMPTY_LD_LIBRARY_PATH_ENV.as_ptr().cast(),
         }
     };
 
+    // Drop the duplicates of the variables we set, as getenv() returns
+    // the first match
+    for shadowed in ctx.shadowed_env {
+        log::info!("Removing duplicate {:?}", AsStr(shadowed.name()));
+        if let Err(e) = args.remove_env(shadowed) {
+            log::warn!("Not removing the duplicate: {e}");
+        }
+    }
+
+    // Set the extra variables for the program
+    for (var, existing) in program_settings.env.iter().zip(&mut ctx.program_env) {
+        let value = var.value.to_bytes();
+        log::info!("Setting {} for the program", var.name);
+
+        if let Some(existing) = existing.take() {
+            existing.edit(None, value.len(), |_, new| {
+                new.copy_from_slice(value);
+            });
+        } else if let Err(e) = args.add_env(var.name, value.len(), |buf| buf.copy_from_slice(value))
+        {
+            log::warn!("Not setting {}: {e}", var.name);
+        }
+    }
+
     let pagesz = args
         .auxv()
         .at_pagesz
@@ -183,17 +326,28 @@ extern "C" fn real_main() -> ! {
         .expect("AT_PAGESZ must exist")
         .value();
 
-    log::info!("Loading {nix_ld:?}");
-    let loader = elf::ElfHandle::open(nix_ld, pagesz).unwrap();
-    let loader_map = loader.map().unwrap();
-
-    let mut at_base = args.auxv_mut().at_base.as_mut().and_then(|base| {
-        if base.value().is_null() {
-            None
+    // When executed directly, the program is only an argument to ld.so
+    // and cannot be checked, so this is denied outright
+    if let Some(allowlist) = config.policy.allowlist {
+        if target.is_some() {
+            allowlist::enforce(allowlist);
         } else {
-            Some(base)
+            allowlist::deny_direct_execution();
         }
-    });
+    }
+
+    log::info!("Loading {nix_ld:?}");
+    let loader = elf::ElfHandle::open(nix_ld, pagesz, &policy)
+        .unwrap_or_else(|e| loader_failed(nix_ld_source, &e));
+    let loader_map = loader
+        .map()
+        .unwrap_or_else(|e| loader_failed(nix_ld_source, &e));
+
+    let mut at_base = args
+        .auxv_mut()
+        .at_base
+        .as_mut()
+        .filter(|base| !base.value().is_null());
 
     match at_base {
         None => {
@@ -203,17 +357,23 @@ extern "C" fn real_main() -> ! {
                 log::warn!("- NIX_LD, {NIX_LD_SYSTEM_ENV}");
                 log::warn!("- NIX_LD_LIBRARY_PATH, {NIX_LD_LIBRARY_PATH_SYSTEM_ENV}");
                 log::warn!("- NIX_LD_LOG (error, warn, info, debug, trace)");
+                log::warn!("Config files honored by nix-ld:");
+                log::warn!("- {:?}", config::SYSTEM_CONFIG);
+                log::warn!("- $XDG_CONFIG_HOME/nix-ld/config");
                 log::warn!("Default ld.so: {DEFAULT_NIX_LD:?}");
             }
 
             args.handoff(|start| unsafe {
                 log::debug!("Start context: {start:#?}");
-                sys::execve(nix_ld.as_ptr(), start.argv, start.envp);
-                sys::abort();
+                let err = sys::execve(nix_ld, start.argv, start.envp).with_path(nix_ld);
+                loader_failed(nix_ld_source, &err);
             });
         }
         Some(ref mut at_base) => {
             // We are the loader - Set the AT_BASE to the actual loader
+            //
+            // Like the kernel, we pass the load bias, which is 0 for
+            // non-relocatable loaders.
             at_base.set(loader_map.load_bias() as *const c_void);
         }
     }
@@ -221,15 +381,17 @@ extern "C" fn real_main() -> ! {
     // We want our LD_LIBRARY_PATH to only affect the loaded binary
     // and not propagate to child processes. To achieve this, we
     // replace the entry point with a trampoline that reverts our
-    // LD_LIBRARY_PATH edit and jumps to the real entry point.
+    // temporary environment edits and jumps to the real entry point.
     if let Some(trampoline) = arch::ENTRY_TRAMPOLINE {
         log::info!("Using entry trampoline");
         if let Some(ref mut at_entry) = args.auxv_mut().at_entry {
             unsafe {
                 arch::TRAMPOLINE_CONTEXT.set_elf_entry(at_entry.value());
-                arch::TRAMPOLINE_CONTEXT.revert_env(&env_edit);
             }
             at_entry.set(trampoline as *const _);
+            if let Err(e) = args.revert_env_on_entry(env_edit) {
+                log::warn!("Not restoring LD_LIBRARY_PATH: {e}");
+            }
         } else {
             log::warn!("No AT_ENTRY found");
         }
@@ -239,9 +401,7 @@ extern "C" fn real_main() -> ! {
         log::debug!("Start context: {start:#?}");
 
         if arch::ENTRY_TRAMPOLINE.is_some() {
-            if let Some(extra_env) = start.extra_env {
-                arch::TRAMPOLINE_CONTEXT.revert_env_entry(extra_env);
-            }
+            arch::TRAMPOLINE_CONTEXT.set_env_reverts(&start.env_reverts);
             log::debug!("Trampoline context: {:#?}", arch::TRAMPOLINE_CONTEXT);
         }
 
@@ -249,3 +409,215 @@ extern "C" fn real_main() -> ! {
         loader_map.jump_with_sp(start.sp);
     });
 }
+
+/// Loads the system-wide and per-user config files.
+///
+/// Settings in the per-user config file override the system-wide ones.
+/// In secure-execution mode, only the system-wide config file is loaded.
+///
+/// The system-wide config file can set policies, so failing to read it
+/// is fatal rather than silently running without them.
+fn load_config(args: &Args, secure: bool) -> Config {
+    let mut config = match Config::load_strict(config::SYSTEM_CONFIG) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("Cannot read {:?}: {e}", config::SYSTEM_CONFIG);
+            sys::exit(127);
+        }
+    };
+    if secure {
+        log::info!("Ignoring the per-user config file in secure-execution mode");
+        return config;
+    }
+
+    // Relative paths in XDG_CONFIG_HOME are invalid and must be ignored
+    let xdg_config_home = args.get_env(b"XDG_CONFIG_HOME").map(CStr::to_bytes);
+    let home = args.get_env(b"HOME").map(CStr::to_bytes);
+    let user_config = match (xdg_config_home, home) {
+        (Some(xdg), _) if xdg.starts_with(b"/") => config::join_path(xdg, config::USER_CONFIG),
+        (_, Some(home)) if home.starts_with(b"/") => {
+            config::join_path(home, config::USER_CONFIG_FALLBACK)
+        }
+        _ => None,
+    };
+
+    if let Some(path) = user_config {
+        let path = CStr::from_bytes_with_nul(&path).unwrap();
+        config.merge(Config::load(path));
+    }
+
+    config
+}
+
+/// Loads the settings specific to the program being loaded.
+///
+/// The matching profile overrides the extended attributes of the
+/// program, which override the sidecar file. Both are ignored in
+/// secure-execution mode.
+fn load_program_settings(
+    target: &Target,
+    config: &Config,
+    policy: &LoaderPolicy,
+    secure: bool,
+) -> Settings {
+    let mut settings = Settings::default();
+
+    if secure {
+        log::info!("Ignoring sidecar files and extended attributes in secure-execution mode");
+    } else if let Some(path) = target.path {
+        if let Some(mut sidecar) = config::load_sidecar(path) {
+            sidecar.settings.nix_ld = sidecar
+                .settings
+                .nix_ld
+                .filter(|nix_ld| is_program_loader_trusted(nix_ld, policy, "the sidecar file"));
+            settings.merge(sidecar.settings);
+        }
+
+        let mut xattrs = config::load_xattrs(path);
+        xattrs.nix_ld = xattrs
+            .nix_ld
+            .filter(|nix_ld| is_program_loader_trusted(nix_ld, policy, "extended attributes"));
+        settings.merge(xattrs);
+    }
+
+    if let Some(profile) = config.find_profile(target) {
+        log::info!("Using profile {:?}", AsStr(profile.name));
+        settings.merge(profile.settings.clone());
+    }
+
+    settings
+}
+
+/// Returns whether the loader named next to a program can be used.
+///
+/// Anyone who can write a sidecar file or extended attributes of a
+/// program can name a loader there, so it must pass the trusted-loader
+/// policy, which must be enabled. `source` names where it came from.
+fn is_program_loader_trusted(nix_ld: &'static CStr, policy: &LoaderPolicy, source: &str) -> bool {
+    if !policy.is_enabled() {
+        log::warn!("Ignoring NIX_LD from {source} without a trusted-loader policy");
+        return false;
+    }
+
+    match sys::File::open_cstr(nix_ld).and_then(|file| policy.check(nix_ld, &file)) {
+        Ok(()) => true,
+        Err(e) => {
+            log::warn!("Ignoring NIX_LD from {source}: {e}");
+            false
+        }
+    }
+}
+
+/// A library path joined from colon-separated path lists.
+///
+/// Empty components would make ld.so search the current directory, so
+/// they are dropped along with duplicates and, if requested, directories
+/// that don't exist.
+struct JoinedPath {
+    components: ArrayVec<&'static [u8], MAX_LIBRARY_PATH_COMPONENTS>,
+}
+
+impl JoinedPath {
+    fn new(
+        head: &[&'static [u8]],
+        middle: &[&'static [u8]],
+        tail: &[&'static [u8]],
+        skip_missing_dirs: bool,
+    ) -> Self {
+        let mut path = Self {
+            components: ArrayVec::new(),
+        };
+
+        let mut dropped = 0;
+        let lists = head.iter().chain(middle).chain(tail);
+        for component in lists.flat_map(|list| list.split(|b| *b == b':')) {
+            if component.is_empty() {
+                log::debug!("Dropping empty library path component");
+            } else if path.components.contains(&component) {
+                log::debug!("Dropping duplicate library path {:?}", AsStr(component));
+            } else if skip_missing_dirs && !sys::is_dir(component) {
+                log::debug!("Dropping missing library path {:?}", AsStr(component));
+            } else if path.components.push(component).is_err() {
+                log::debug!(
+                    "Dropping library path {:?} over the limit",
+                    AsStr(component)
+                );
+                dropped += 1;
+            }
+        }
+
+        if dropped != 0 {
+            log::warn!(
+                "More than {MAX_LIBRARY_PATH_COMPONENTS} library paths - Dropping the last {dropped}"
+            );
+        }
+
+        path
+    }
+
+    fn len(&self) -> usize {
+        let seps = self.components.len().saturating_sub(1);
+        self.components.iter().map(|c| c.len()).sum::<usize>() + seps
+    }
+
+    /// Writes the joined path, filling the entire buffer.
+    fn write(&self, buf: &mut [u8]) {
+        let mut pos = 0;
+        for (i, component) in self.components.iter().enumerate() {
+            if i != 0 {
+                buf[pos] = b':';
+                pos += 1;
+            }
+            buf[pos..pos + component.len()].copy_from_slice(component);
+            pos += component.len();
+        }
+        debug_assert_eq!(pos, buf.len());
+    }
+}
+
+/// Where the path to the loader came from.
+#[derive(Clone, Copy)]
+enum LoaderSource {
+    /// Settings for the program
+    Program,
+
+    /// An environment variable
+    Env(&'static [u8]),
+
+    /// A config file
+    Config,
+
+    /// The built-in default
+    Default,
+}
+
+impl fmt::Display for LoaderSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Program => f.write_str("settings for the program"),
+            Self::Env(name) => write!(f, "${}", core::str::from_utf8(name).unwrap_or("NIX_LD")),
+            Self::Config => f.write_str("config file"),
+            Self::Default => f.write_str("built-in default"),
+        }
+    }
+}
+
+/// Exits like a shell would when the interpreter cannot be run.
+fn loader_failed(source: LoaderSource, err: &sys::Error) -> ! {
+    log::error!("Cannot run ld.so from {source}: {err}");
+    log::error!(
+        "Hint: On NixOS, set `programs.nix-ld.enable = true;` to provide a default ld.so. \
+         Otherwise, set NIX_LD to the ld.so of your system."
+    );
+    sys::exit(127)
+}
+
+fn set_log_level(log_level: &CStr) {
+    if let Ok(log_level) = log_level.to_str() {
+        if let Ok(level) = log_level.parse::<log::LevelFilter>() {
+            log::set_max_level(level);
+        } else {
+            log::warn!("Unknown log level {log_level}");
+        }
+    }
+}
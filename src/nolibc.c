@@ -1,7 +0,0 @@
-#ifndef __PIE__
-#error Pass -fPIE
-#endif
-
-// HACK
-#define static
-#include "nolibc.h"
@@ -1,8 +1,11 @@
 //! Low-level support.
 
 use core::fmt::Write;
+use core::mem;
+use core::ptr;
 
 use crate::arch::STACK_ALIGNMENT;
+use crate::auxv::AuxVec;
 use crate::sys;
 
 pub static LOGGER: StderrLogger = StderrLogger;
@@ -43,12 +46,10 @@ impl StackSpace {
 pub fn explode(s: &str) -> ! {
     let prefix = "[nix-ld] FATAL: ";
 
-    unsafe {
-        sys::write(2, prefix.as_ptr(), prefix.len());
-        sys::write(2, s.as_ptr(), s.len());
-        sys::write(2, "\n".as_ptr(), 1);
-        sys::abort();
-    }
+    let _ = sys::write(2, prefix.as_bytes());
+    let _ = sys::write(2, s.as_bytes());
+    let _ = sys::write(2, b"\n");
+    sys::abort();
 }
 
 #[cfg(not(test))]
@@ -57,9 +58,7 @@ fn panic_handler(info: &core::panic::PanicInfo) -> ! {
     let mut stderr = sys::stderr();
     writeln!(stderr, "[nix-ld] FATAL: {info}").unwrap();
 
-    unsafe {
-        sys::abort();
-    }
+    sys::abort();
 }
 
 #[unsafe(no_mangle)]
@@ -67,11 +66,207 @@ extern "C" fn __stack_chk_fail() -> ! {
     explode("stack smashing detected");
 }
 
-// Stack canary value for stack protection. In a production system, this would
-// be randomized at startup, but for nix-ld's minimal environment, a constant
-// suffices to satisfy the linker requirements from libcompiler_builtins.
+// Stack canary value for stack protection. This is only used until
+// init_stack_guard() seeds it from AT_RANDOM, or if the kernel doesn't
+// provide AT_RANDOM.
+#[unsafe(no_mangle)]
+static mut __stack_chk_guard: usize = 0x1234567890abcdef_u64 as usize;
+
+/// Clears the first byte of the canary in memory.
+const FIRST_BYTE_MASK: usize = if cfg!(target_endian = "little") {
+    !0xff
+} else {
+    !(0xff << (usize::BITS - 8))
+};
+
+/// Seeds the stack protector canary from `AT_RANDOM`.
+///
+/// Protected functions that are on the stack when the canary changes
+/// will fail their check on return, so this must be called before any
+/// of them. Our caller never returns, and this function is kept out of
+/// line without any local arrays so `-Z stack-protector=strong` leaves
+/// it alone. Rust cannot exempt a single function from protection, so
+/// `-Z stack-protector=all` would make it fail its own check.
+#[inline(never)]
+pub unsafe fn init_stack_guard(envp: *const *const u8) {
+    unsafe {
+        let auxv = AuxVec::from_envp(envp);
+        let Some(random) = auxv.at_random.as_ref().map(|e| e.value()) else {
+            return;
+        };
+        if random.is_null() {
+            return;
+        }
+
+        // Like glibc, the first byte is zero so string functions can't
+        // read or write past the canary
+        let random = ptr::read_unaligned(random.cast::<usize>());
+        __stack_chk_guard = random & FIRST_BYTE_MASK;
+    }
+}
+
+// Without a libc, we provide the memory functions that the compiler
+// emits calls to. LLVM doesn't turn the loops in functions with these
+// names back into calls to themselves, except in memmove where we use
+// volatile stores to be sure.
+//
+// They work a word at a time when both pointers can be aligned at the
+// same time. We don't use unaligned loads, which may be lowered into
+// calls to memcpy in debug builds.
+
+const WORD_SIZE: usize = mem::size_of::<usize>();
+
+/// Returns how many bytes to handle one at a time before `a` and `b`
+/// are word-aligned, or `n` if they cannot both be.
+fn head_len(a: *const u8, b: *const u8, n: usize) -> usize {
+    if !(a as usize ^ b as usize).is_multiple_of(WORD_SIZE) {
+        return n;
+    }
+    core::cmp::min(a.align_offset(WORD_SIZE), n)
+}
+
+#[unsafe(no_mangle)]
+unsafe extern "C" fn memcpy(dst: *mut u8, src: *const u8, n: usize) -> *mut u8 {
+    unsafe {
+        let head = head_len(dst, src, n);
+        let words = (n - head) / WORD_SIZE;
+
+        let mut i = 0;
+        while i < head {
+            *dst.add(i) = *src.add(i);
+            i += 1;
+        }
+        for _ in 0..words {
+            *dst.add(i).cast::<usize>() = *src.add(i).cast::<usize>();
+            i += WORD_SIZE;
+        }
+        while i < n {
+            *dst.add(i) = *src.add(i);
+            i += 1;
+        }
+    }
+    dst
+}
+
+#[unsafe(no_mangle)]
+unsafe extern "C" fn memmove(dst: *mut u8, src: *const u8, n: usize) -> *mut u8 {
+    unsafe {
+        if (dst as usize) < (src as usize) {
+            // Copying forwards only overwrites what we've already read
+            let head = head_len(dst, src, n);
+            let words = (n - head) / WORD_SIZE;
+
+            let mut i = 0;
+            while i < head {
+                dst.add(i).write_volatile(*src.add(i));
+                i += 1;
+            }
+            for _ in 0..words {
+                let word = *src.add(i).cast::<usize>();
+                dst.add(i).cast::<usize>().write_volatile(word);
+                i += WORD_SIZE;
+            }
+            while i < n {
+                dst.add(i).write_volatile(*src.add(i));
+                i += 1;
+            }
+        } else {
+            // Copying backwards, the ends get aligned first
+            let tail = if head_len(dst, src, n) == n {
+                n
+            } else {
+                (dst as usize + n) % WORD_SIZE
+            };
+            let words = (n - tail) / WORD_SIZE;
+
+            let mut i = n;
+            while i > n - tail {
+                i -= 1;
+                dst.add(i).write_volatile(*src.add(i));
+            }
+            for _ in 0..words {
+                i -= WORD_SIZE;
+                let word = *src.add(i).cast::<usize>();
+                dst.add(i).cast::<usize>().write_volatile(word);
+            }
+            while i > 0 {
+                i -= 1;
+                dst.add(i).write_volatile(*src.add(i));
+            }
+        }
+    }
+    dst
+}
+
+#[unsafe(no_mangle)]
+unsafe extern "C" fn memset(dst: *mut u8, c: i32, n: usize) -> *mut u8 {
+    unsafe {
+        let byte = c as u8;
+        let word = usize::MAX / 0xff * usize::from(byte);
+        let head = head_len(dst, dst, n);
+        let words = (n - head) / WORD_SIZE;
+
+        let mut i = 0;
+        while i < head {
+            *dst.add(i) = byte;
+            i += 1;
+        }
+        for _ in 0..words {
+            *dst.add(i).cast::<usize>() = word;
+            i += WORD_SIZE;
+        }
+        while i < n {
+            *dst.add(i) = byte;
+            i += 1;
+        }
+    }
+    dst
+}
+
 #[unsafe(no_mangle)]
-static __stack_chk_guard: usize = 0x1234567890abcdef_u64 as usize;
+unsafe extern "C" fn memcmp(a: *const u8, b: *const u8, n: usize) -> i32 {
+    unsafe {
+        let head = head_len(a, b, n);
+
+        let mut i = 0;
+        while i < head {
+            let (x, y) = (*a.add(i), *b.add(i));
+            if x != y {
+                return i32::from(x) - i32::from(y);
+            }
+            i += 1;
+        }
+
+        // Skip over equal words, then find the first differing byte
+        while n - i >= WORD_SIZE && *a.add(i).cast::<usize>() == *b.add(i).cast::<usize>() {
+            i += WORD_SIZE;
+        }
+        while i < n {
+            let (x, y) = (*a.add(i), *b.add(i));
+            if x != y {
+                return i32::from(x) - i32::from(y);
+            }
+            i += 1;
+        }
+    }
+    0
+}
+
+#[unsafe(no_mangle)]
+unsafe extern "C" fn bcmp(a: *const u8, b: *const u8, n: usize) -> i32 {
+    unsafe { memcmp(a, b, n) }
+}
+
+// Reading whole words could go past the end of the string, so this
+// stays a byte at a time. It's only used on short strings.
+#[unsafe(no_mangle)]
+unsafe extern "C" fn strlen(s: *const u8) -> usize {
+    let mut len = 0;
+    while unsafe { *s.add(len) } != 0 {
+        len += 1;
+    }
+    len
+}
 
 // On 32-bit systems, PIC code uses __stack_chk_fail_local instead of __stack_chk_fail
 // as an optimization to avoid going through the PLT
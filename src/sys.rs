@@ -1,16 +1,15 @@
 //! System interface.
 //!
-//! We currently use `nolibc` for the following things:
+//! We talk to the kernel directly. The `_start` code and the raw
+//! system calls are in `arch.rs`, and this module wraps them into
+//! functions that return the errno as an [`Error`].
 //!
-//! - `_start` code
-//! - Thin syscall wrappers like `open()`, `read()`, `write()` (can be
-//!   replaced by `syscalls`)
-//!
-//! This dependency may be reduced further. For memory operations,
-//! compiler-builtins provides faster implementations.
+//! The memory functions the compiler emits calls to are in
+//! `support.rs`.
 
-use core::ffi::{CStr, c_char, c_int, c_void};
+use core::ffi::{CStr, c_int, c_void};
 use core::fmt;
+use core::mem::MaybeUninit;
 use core::ptr;
 use core::slice;
 
@@ -18,82 +17,212 @@ use embedded_io as eio;
 pub use embedded_io::{Read, Write};
 #[rustfmt::skip]
 pub use linux_raw_sys::general::{
-    O_RDONLY,
+    O_RDONLY, PATH_MAX,
     PROT_NONE, PROT_READ, PROT_WRITE, PROT_EXEC,
-    MAP_PRIVATE, MAP_FIXED, MAP_ANONYMOUS,
+    MAP_PRIVATE, MAP_FIXED, MAP_FIXED_NOREPLACE, MAP_ANONYMOUS,
+    AT_EMPTY_PATH, AT_FDCWD, STATX_MODE, STATX_TYPE, STATX_UID,
+    S_IFDIR, S_IFMT, S_IFREG, S_ISVTX, S_IWGRP, S_IWOTH,
+    statx as Statx,
+};
+#[rustfmt::skip]
+use linux_raw_sys::general::{
+    SIGABRT,
+    __NR_close, __NR_execve, __NR_exit_group, __NR_getpid, __NR_getxattr,
+    __NR_kill, __NR_mprotect, __NR_munmap, __NR_openat, __NR_pread64,
+    __NR_read, __NR_readlinkat, __NR_statx, __NR_write,
 };
 use heapless::Vec as ArrayVec;
 pub use linux_raw_sys::errno;
+#[cfg(target_pointer_width = "64")]
+use linux_raw_sys::general::__NR_mmap;
+#[cfg(target_pointer_width = "32")]
+use linux_raw_sys::general::__NR_mmap2;
 
-#[link(name = "c_kinda", kind = "static")]
-unsafe extern "C" {
-    pub fn write(fd: i32, buf: *const u8, count: usize) -> isize;
-    #[must_use]
-    pub fn mmap(
-        addr: *mut c_void,
-        len: usize,
-        prot: u32,
-        flags: u32,
-        fd: i32,
-        offset: isize,
-    ) -> *mut c_void;
-    pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
-    pub fn open(path: *const c_char, oflag: u32, _: ...) -> c_int;
-    pub fn read(fd: i32, buf: *mut c_void, count: usize) -> isize;
-    pub fn close(fd: i32) -> c_int;
-    pub fn abort() -> !;
-    pub fn memset(dst: *mut c_void, c: c_int, n: usize) -> *mut c_void;
-    pub fn execve(prog: *const c_char, argv: *const *const u8, envp: *const *const u8) -> c_int;
-
-    #[link_name = "errno"]
-    static c_errno: u32;
-}
-
-pub const MAP_FAILED: *mut c_void = !0 as *mut c_void;
-
-macro_rules! if_ok {
-    ($ret:ident, $expr:expr) => {
-        if $ret < 0 {
-            Err(Error::Posix(errno()))
-        } else {
-            Ok($expr)
-        }
-    };
-    ($ret:ident $($rest:tt)+) => {
-        if_ok!($ret, $ret $($rest)*)
+use crate::arch;
+pub use crate::error::{Error, ErrorKind};
+
+/// Performs a system call with up to six arguments.
+unsafe fn syscall<const N: usize>(
+    op: &'static str,
+    nr: u32,
+    args: [usize; N],
+) -> Result<usize, Error> {
+    let mut all = [0; 6];
+    all[..N].copy_from_slice(&args);
+
+    let ret = unsafe { arch::syscall(nr, all) };
+
+    // The last 4095 values are errnos, anything else is a result
+    if ret > -4096isize as usize {
+        Err(Error::new(ErrorKind::Os(ret.wrapping_neg() as u32)).with_op(op))
+    } else {
+        Ok(ret)
+    }
+}
+
+pub fn write(fd: c_int, buf: &[u8]) -> Result<usize, Error> {
+    unsafe {
+        syscall(
+            "write",
+            __NR_write,
+            [fd as usize, buf.as_ptr() as usize, buf.len()],
+        )
+    }
+}
+
+pub unsafe fn mmap(
+    addr: *mut c_void,
+    len: usize,
+    prot: u32,
+    flags: u32,
+    fd: c_int,
+    offset: usize,
+) -> Result<*mut c_void, Error> {
+    let args = [
+        addr as usize,
+        len,
+        prot as usize,
+        flags as usize,
+        fd as usize,
+        offset,
+    ];
+
+    // The offset is in 4096-byte units
+    #[cfg(target_pointer_width = "32")]
+    let ret = unsafe {
+        let mut args = args;
+        args[5] /= 4096;
+        syscall("mmap", __NR_mmap2, args)
     };
+
+    // The arguments are passed in memory
+    #[cfg(target_arch = "s390x")]
+    let ret = unsafe { syscall("mmap", __NR_mmap, [args.as_ptr() as usize]) };
+
+    #[cfg(all(target_pointer_width = "64", not(target_arch = "s390x")))]
+    let ret = unsafe { syscall("mmap", __NR_mmap, args) };
+
+    ret.map(|addr| addr as *mut c_void)
+}
+
+pub unsafe fn munmap(addr: *mut c_void, len: usize) -> Result<(), Error> {
+    unsafe { syscall("munmap", __NR_munmap, [addr as usize, len]).map(drop) }
+}
+
+pub unsafe fn mprotect(addr: *mut c_void, len: usize, prot: u32) -> Result<(), Error> {
+    unsafe {
+        syscall(
+            "mprotect",
+            __NR_mprotect,
+            [addr as usize, len, prot as usize],
+        )
+        .map(drop)
+    }
+}
+
+/// Runs a program, returning only on failure.
+pub unsafe fn execve(prog: &CStr, argv: *const *const u8, envp: *const *const u8) -> Error {
+    let args = [prog.as_ptr() as usize, argv as usize, envp as usize];
+    match unsafe { syscall("execve", __NR_execve, args) } {
+        Ok(_) => unreachable!(),
+        Err(err) => err,
+    }
+}
+
+pub fn exit(status: c_int) -> ! {
+    loop {
+        let _ = unsafe { syscall("exit_group", __NR_exit_group, [status as usize]) };
+    }
+}
+
+pub fn abort() -> ! {
+    unsafe {
+        if let Ok(pid) = syscall("getpid", __NR_getpid, []) {
+            let _ = syscall("kill", __NR_kill, [pid, SIGABRT as usize]);
+        }
+    }
+    exit(127)
+}
+
+fn openat(path: &CStr) -> Result<c_int, Error> {
+    let args = [AT_FDCWD as usize, path.as_ptr() as usize, O_RDONLY as usize];
+    unsafe { syscall("open", __NR_openat, args).map(|fd| fd as c_int) }
+}
+
+unsafe fn pread(fd: c_int, buf: &mut [u8], offset: usize) -> Result<usize, Error> {
+    let (fd, ptr, len) = (fd as usize, buf.as_mut_ptr() as usize, buf.len());
+
+    #[cfg(target_pointer_width = "64")]
+    let args = [fd, ptr, len, offset];
+
+    // 64-bit arguments start at an even register
+    #[cfg(target_arch = "arm")]
+    let args = [fd, ptr, len, 0, offset, 0];
+
+    // The offset is split into two words, and we never read past 4 GiB
+    #[cfg(all(target_pointer_width = "32", not(target_arch = "arm")))]
+    let args = [fd, ptr, len, offset, 0];
+
+    unsafe { syscall("pread", __NR_pread64, args) }
+}
+
+unsafe fn statx(dirfd: c_int, path: &CStr, flags: u32, mask: u32) -> Result<Statx, Error> {
+    let mut buf = MaybeUninit::<Statx>::zeroed();
+    let args = [
+        dirfd as usize,
+        path.as_ptr() as usize,
+        flags as usize,
+        mask as usize,
+        buf.as_mut_ptr() as usize,
+    ];
+    unsafe {
+        syscall("statx", __NR_statx, args)?;
+        Ok(buf.assume_init())
+    }
 }
 
+/// A NUL-terminated path on the stack.
+pub type PathBuf = ArrayVec<u8, { PATH_MAX as usize }>;
+
 /// A file.
 #[derive(Debug)]
 pub struct File(c_int);
 
-/// An error.
-///
-/// TODO: Convert to human-readable form.
-#[derive(Debug)]
-pub enum Error {
-    Posix(u32),
-    PathTooLong,
-    Unknown,
-}
-
 impl File {
     /// Opens a file.
     ///
     /// This copies the path into a temporary buffer to convert it
     /// into a NUL-terminated string.
     pub fn open(path: &[u8]) -> Result<Self, Error> {
-        let mut temp = ArrayVec::<_, 100>::from_slice(path).map_err(|_| Error::PathTooLong)?;
-        temp.push(0).map_err(|_| Error::PathTooLong)?;
-        let ret = unsafe { open(temp.as_ptr().cast(), O_RDONLY, 0) };
-        if_ok!(ret, Self(ret))
+        let mut temp = ArrayVec::<_, 100>::from_slice(path)
+            .map_err(|_| Error::new(ErrorKind::PathTooLong).with_op("open"))?;
+        temp.push(0)
+            .map_err(|_| Error::new(ErrorKind::PathTooLong).with_op("open"))?;
+        let path = CStr::from_bytes_until_nul(&temp).unwrap();
+        openat(path).map(Self)
     }
 
     /// Opens a file.
     pub fn open_cstr(path: &CStr) -> Result<Self, Error> {
-        let ret = unsafe { open(path.as_ptr(), O_RDONLY, 0) };
-        if_ok!(ret, Self(ret))
+        openat(path).map(Self)
+    }
+
+    /// Returns the status of the file.
+    pub fn statx(&self, mask: u32) -> Result<Statx, Error> {
+        unsafe { statx(self.0, c"", AT_EMPTY_PATH, mask) }
+    }
+
+    /// Reads exactly `buf.len()` bytes at an offset.
+    pub fn read_exact_at(&self, mut buf: &mut [u8], mut offset: usize) -> Result<(), Error> {
+        while !buf.is_empty() {
+            let ret = unsafe { pread(self.0, buf, offset)? };
+            if ret == 0 {
+                return Err(Error::new(ErrorKind::UnexpectedEof).with_op("pread"));
+            }
+            buf = &mut buf[ret..];
+            offset += ret;
+        }
+        Ok(())
     }
 
     /// Returns the underlying file descriptor number.
@@ -105,22 +234,21 @@ impl File {
 impl Drop for File {
     fn drop(&mut self) {
         if self.0 > 2 {
-            unsafe { close(self.0) };
+            let _ = unsafe { syscall("close", __NR_close, [self.0 as usize]) };
         }
     }
 }
 
 impl Read for File {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
-        let ret = unsafe { read(self.0, buf.as_mut_ptr().cast(), buf.len()) };
-        if_ok!(ret as usize)
+        let args = [self.0 as usize, buf.as_mut_ptr() as usize, buf.len()];
+        unsafe { syscall("read", __NR_read, args) }
     }
 }
 
 impl Write for File {
     fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
-        let ret = unsafe { write(self.0, buf.as_ptr(), buf.len()) };
-        if_ok!(ret as usize)
+        write(self.0, buf)
     }
     fn flush(&mut self) -> Result<(), Self::Error> {
         Ok(())
@@ -145,22 +273,11 @@ impl eio::Error for Error {
     }
 }
 
-impl PartialEq<u32> for Error {
-    fn eq(&self, other: &u32) -> bool {
-        matches!(self, Self::Posix(num) if num == other)
-    }
-}
-
 pub const fn stderr() -> impl fmt::Write {
     File(2)
 }
 
-#[inline(always)]
-pub fn errno() -> u32 {
-    unsafe { c_errno }
-}
-
-pub fn new_slice_leak(size: usize) -> Option<&'static mut [u8]> {
+pub fn new_slice_leak(size: usize) -> Result<&'static mut [u8], Error> {
     let ptr = unsafe {
         mmap(
             ptr::null_mut(),
@@ -169,14 +286,64 @@ pub fn new_slice_leak(size: usize) -> Option<&'static mut [u8]> {
             MAP_PRIVATE | MAP_ANONYMOUS,
             -1,
             0,
-        )
+        )?
     };
 
-    if ptr == MAP_FAILED {
-        None
-    } else {
-        Some(unsafe { slice::from_raw_parts_mut(ptr as *mut u8, size) })
+    Ok(unsafe { slice::from_raw_parts_mut(ptr as *mut u8, size) })
+}
+
+/// Returns the status of a path, following symbolic links.
+pub fn stat(path: &CStr, mask: u32) -> Result<Statx, Error> {
+    unsafe { statx(AT_FDCWD, path, 0, mask) }
+}
+
+/// Returns whether a path is an existing directory.
+pub fn is_dir(path: &[u8]) -> bool {
+    let mut temp = PathBuf::new();
+    if temp.extend_from_slice(path).is_err() || temp.push(0).is_err() {
+        return false;
+    }
+
+    let path = CStr::from_bytes_until_nul(&temp).unwrap();
+    match unsafe { statx(AT_FDCWD, path, 0, STATX_TYPE) } {
+        Ok(stat) => u32::from(stat.stx_mode) & S_IFMT == S_IFDIR,
+        Err(_) => false,
+    }
+}
+
+/// Reads the target of a symbolic link into a NUL-terminated buffer.
+pub fn read_link(path: &CStr) -> Result<PathBuf, Error> {
+    let mut buf = PathBuf::new();
+    let args = [
+        AT_FDCWD as usize,
+        path.as_ptr() as usize,
+        buf.as_mut_ptr() as usize,
+        buf.capacity(),
+    ];
+    let len = unsafe { syscall("readlink", __NR_readlinkat, args)? };
+
+    // A target filling the entire buffer may have been truncated
+    unsafe {
+        buf.set_len(len);
     }
+    buf.push(0)
+        .map_err(|_| Error::new(ErrorKind::PathTooLong).with_op("readlink"))?;
+    Ok(buf)
+}
+
+/// Reads an extended attribute of a file into a leaked buffer.
+///
+/// The returned value is followed by a NUL terminator.
+pub fn getxattr_leak(path: &CStr, name: &CStr) -> Result<&'static mut [u8], Error> {
+    let args = [path.as_ptr() as usize, name.as_ptr() as usize];
+    let size = unsafe { syscall("getxattr", __NR_getxattr, args)? };
+
+    let buf = new_slice_leak(size + 1)?;
+    let args = [args[0], args[1], buf.as_mut_ptr() as usize, size];
+    let len = unsafe { syscall("getxattr", __NR_getxattr, args)? };
+    buf[len] = 0;
+
+    Ok(&mut buf[..=len])
 }
 
 #[cfg(not(test))]
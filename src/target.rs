@@ -0,0 +1,149 @@
+//! Information about the program being loaded.
+//!
+//! Programs can declare the library paths they need in a note with
+//! the owner `nix-ld` and the type `NT_NIX_LD_LIBRARY_PATH`, whose
+//! description is a colon-separated list of paths:
+//!
+//! ```c
+//! #define LIBRARY_PATH "/opt/foo/lib:/nix/store/...-bar/lib"
+//!
+//! __attribute__((section(".note.nix-ld"), aligned(4), used))
+//! static const struct {
+//!     uint32_t namesz, descsz, type;
+//!     char name[8];
+//!     char desc[(sizeof(LIBRARY_PATH) + 3) & ~3];
+//! } nix_ld_note = { 7, sizeof(LIBRARY_PATH), 1, "nix-ld", LIBRARY_PATH };
+//! ```
+
+use core::ffi::CStr;
+use core::slice;
+
+use crate::args::Args;
+use crate::elf::{
+    Note, NoteIter, ProgramHeaders,
+    elf_types::program_header::{PT_NOTE, PT_PHDR},
+};
+use crate::sys::{self, Error as IoError, PathBuf};
+use goblin::elf::note::NT_GNU_BUILD_ID;
+use heapless::Vec as ArrayVec;
+
+/// The program being loaded, as seen by the kernel.
+pub const SELF_EXE: &CStr = c"/proc/self/exe";
+
+/// The owner of notes for nix-ld.
+const NOTE_OWNER: &[u8] = b"nix-ld";
+
+/// A note declaring library paths.
+const NT_NIX_LD_LIBRARY_PATH: u32 = 1;
+
+/// The maximum number of library path notes.
+const MAX_LIBRARY_PATH_NOTES: usize = 8;
+
+/// The program we are loading.
+#[derive(Debug, Default)]
+pub struct Target {
+    /// The path the program was executed with (`AT_EXECFN`).
+    pub path: Option<&'static CStr>,
+
+    /// The basename of `argv[0]`.
+    pub name: Option<&'static [u8]>,
+
+    /// The GNU build-id of the program.
+    pub build_id: Option<&'static [u8]>,
+
+    /// Colon-separated library paths declared in the notes of the program.
+    pub library_paths: ArrayVec<&'static [u8], MAX_LIBRARY_PATH_NOTES>,
+}
+
+impl Target {
+    /// Identifies the program from the auxiliary vector.
+    ///
+    /// This is only meaningful when we are the loader. When executed
+    /// directly, the auxiliary vector describes nix-ld itself.
+    pub fn from_args(args: &Args) -> Self {
+        let auxv = args.auxv();
+
+        let name = args.arg(0).map(|argv0| {
+            let argv0 = argv0.to_bytes();
+            match argv0.iter().rposition(|b| *b == b'/') {
+                Some(slash) => &argv0[slash + 1..],
+                None => argv0,
+            }
+        });
+
+        let phs = match (&auxv.at_phdr, &auxv.at_phent, &auxv.at_phnum) {
+            (Some(phdr), Some(phent), Some(phnum)) if !phdr.value().is_null() => unsafe {
+                Some(ProgramHeaders::from_raw(
+                    phdr.value(),
+                    phent.value(),
+                    phnum.value(),
+                ))
+            },
+            _ => None,
+        };
+
+        let mut target = Self {
+            path: auxv.execfn(),
+            name,
+            ..Self::default()
+        };
+
+        if let Some(phs) = &phs {
+            for note in notes(phs) {
+                match (note.name, note.n_type) {
+                    (b"GNU", NT_GNU_BUILD_ID) => {
+                        target.build_id.get_or_insert(note.desc);
+                    }
+                    (NOTE_OWNER, NT_NIX_LD_LIBRARY_PATH) => {
+                        // The description may be padded with NULs
+                        let len = note
+                            .desc
+                            .iter()
+                            .position(|b| *b == 0)
+                            .unwrap_or(note.desc.len());
+                        if target.library_paths.push(&note.desc[..len]).is_err() {
+                            log::warn!("Too many library path notes - Ignoring the rest");
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        target
+    }
+}
+
+/// Resolves the real path of the program, without a NUL terminator.
+///
+/// Unlike `AT_EXECFN`, which may be a symlink chosen by the caller,
+/// this is the file the kernel actually executed.
+pub fn real_path() -> Result<PathBuf, IoError> {
+    let mut path = sys::read_link(SELF_EXE)?;
+    path.pop();
+    Ok(path)
+}
+
+/// Returns an iterator over the notes of the mapped program.
+fn notes(phs: &ProgramHeaders) -> impl Iterator<Item = Note<'static>> + '_ {
+    // The program headers are mapped as part of the program, so
+    // PT_PHDR tells us the load bias
+    let load_bias = phs
+        .iter()
+        .find(|ph| ph.p_type == PT_PHDR)
+        .map(|phdr| (phs.as_ptr() as usize).wrapping_sub(phdr.p_vaddr as usize));
+
+    load_bias.into_iter().flat_map(move |load_bias| {
+        phs.iter()
+            .filter(|ph| ph.p_type == PT_NOTE)
+            .flat_map(move |ph| {
+                let data = unsafe {
+                    slice::from_raw_parts(
+                        load_bias.wrapping_add(ph.p_vaddr as usize) as *const u8,
+                        ph.p_filesz as usize,
+                    )
+                };
+                NoteIter::new(data, ph.p_align as usize)
+            })
+    })
+}
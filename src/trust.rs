@@ -0,0 +1,155 @@
+//! Trusted-loader policy.
+//!
+//! On hardened hosts, the loader named by `NIX_LD` can be restricted
+//! to files that:
+//!
+//! - Live under one of the trusted prefixes (e.g., `/nix/store`)
+//! - Are owned by root
+//! - Are not writable by the group or others
+//!
+//! The policy is enabled by setting trusted prefixes at compile time
+//! with `NIX_LD_TRUSTED_PREFIXES` (colon-separated) or with
+//! `trusted-loader-prefix` in the system-wide config file.
+//!
+//! The checks are done on the file descriptor the loader is mapped
+//! from, so the file cannot be swapped out in between.
+
+use core::ffi::CStr;
+
+use heapless::Vec as ArrayVec;
+
+use crate::config::AsStr;
+use crate::sys::{
+    self, Error as IoError, ErrorKind, File, PathBuf, S_IFMT, S_IFREG, S_IWGRP, S_IWOTH,
+    STATX_MODE, STATX_UID,
+};
+
+/// Trusted prefixes set at compile time.
+const TRUSTED_PREFIXES: Option<&str> = option_env!("NIX_LD_TRUSTED_PREFIXES");
+
+/// The maximum number of trusted prefixes.
+pub const MAX_TRUSTED_PREFIXES: usize = 16;
+
+/// A policy restricting which loaders can be used.
+#[derive(Debug)]
+pub struct LoaderPolicy {
+    prefixes: ArrayVec<&'static [u8], MAX_TRUSTED_PREFIXES>,
+}
+
+impl LoaderPolicy {
+    /// Creates a policy with the prefixes set at compile time.
+    pub fn new() -> Self {
+        let mut policy = Self {
+            prefixes: ArrayVec::new(),
+        };
+
+        if let Some(prefixes) = TRUSTED_PREFIXES {
+            for prefix in prefixes.as_bytes().split(|b| *b == b':') {
+                policy.add_prefix(prefix);
+            }
+        }
+
+        policy
+    }
+
+    /// Trusts loaders under a prefix.
+    pub fn add_prefix(&mut self, prefix: &'static [u8]) {
+        if prefix.is_empty() {
+            return;
+        }
+
+        if !prefix.starts_with(b"/") {
+            log::warn!("Ignoring relative trusted prefix {:?}", AsStr(prefix));
+            return;
+        }
+
+        // Trailing slashes don't change the meaning
+        let mut trimmed = prefix;
+        while let Some(stripped) = trimmed.strip_suffix(b"/") {
+            trimmed = stripped;
+        }
+
+        if self.prefixes.push(trimmed).is_err() {
+            log::warn!("Too many trusted prefixes - Ignoring {:?}", AsStr(prefix));
+        }
+    }
+
+    /// Returns whether the policy restricts anything.
+    pub fn is_enabled(&self) -> bool {
+        !self.prefixes.is_empty()
+    }
+
+    /// Checks whether an open loader can be trusted.
+    pub fn check(&self, path: &'static CStr, file: &File) -> Result<(), IoError> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        let untrusted = |kind: ErrorKind| Err(IoError::new(kind).with_op("trust").with_path(path));
+
+        let stat = file
+            .statx(STATX_UID | STATX_MODE)
+            .map_err(|e| e.with_path(path))?;
+
+        let mode = u32::from(stat.stx_mode);
+        if mode & S_IFMT != S_IFREG {
+            return untrusted(ErrorKind::NotRegularFile);
+        }
+
+        if stat.stx_uid != 0 {
+            return untrusted(ErrorKind::NotOwnedByRoot { uid: stat.stx_uid });
+        }
+
+        if mode & (S_IWGRP | S_IWOTH) != 0 {
+            return untrusted(ErrorKind::Writable { mode });
+        }
+
+        // The path may contain symlinks, so we check where the file
+        // we've actually opened is
+        let fd_path = fd_path(file);
+        let real_path = sys::read_link(CStr::from_bytes_with_nul(&fd_path).unwrap())
+            .map_err(|e| e.with_path(path))?;
+        let real_path = &real_path[..real_path.len() - 1];
+
+        if !self
+            .prefixes
+            .iter()
+            .any(|prefix| has_path_prefix(real_path, prefix))
+        {
+            log::error!("{path:?} resolves to {:?}", AsStr(real_path));
+            return untrusted(ErrorKind::UntrustedPath);
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the `/proc/self/fd` path of an open file.
+fn fd_path(file: &File) -> PathBuf {
+    let mut path = PathBuf::new();
+    path.extend_from_slice(b"/proc/self/fd/").unwrap();
+
+    let mut digits = [0u8; 10];
+    let mut fd = file.as_raw_fd() as u32;
+    let mut start = digits.len();
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (fd % 10) as u8;
+        fd /= 10;
+        if fd == 0 {
+            break;
+        }
+    }
+
+    path.extend_from_slice(&digits[start..]).unwrap();
+    path.push(0).unwrap();
+    path
+}
+
+/// Returns whether a path is under a prefix, respecting component boundaries.
+fn has_path_prefix(path: &[u8], prefix: &[u8]) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with(b"/"),
+        None => false,
+    }
+}
@@ -0,0 +1,78 @@
+#include <inttypes.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/auxv.h>
+
+extern char **environ;
+
+// Uses about 1 KiB of stack per level
+static int recurse(int depth) {
+	volatile char buf[1024];
+	memset((char *)buf, depth, sizeof(buf));
+	if (depth == 0) {
+		return buf[0];
+	}
+	return recurse(depth - 1) + buf[1];
+}
+
+// Returns whether p is in the same mapping as the stack
+static int on_stack(const void *p) {
+	char line[4096];
+	uintptr_t local = (uintptr_t)&line;
+	int found = 0;
+
+	FILE *maps = fopen("/proc/self/maps", "r");
+	if (!maps) {
+		return 0;
+	}
+
+	while (fgets(line, sizeof(line), maps)) {
+		uintptr_t start, end;
+		if (sscanf(line, "%" SCNxPTR "-%" SCNxPTR, &start, &end) != 2) {
+			continue;
+		}
+		if (start <= local && local < end) {
+			found = start <= (uintptr_t)p && (uintptr_t)p < end;
+			break;
+		}
+	}
+
+	fclose(maps);
+	return found;
+}
+
+// Renames the process in place like setproctitle() and checks that
+// the kernel sees it
+static int rewrite_cmdline(char *arg) {
+	char cmdline[4096] = {0};
+	size_t len = strlen(arg);
+	memset(arg, 'x', len);
+
+	FILE *f = fopen("/proc/self/cmdline", "r");
+	if (!f) {
+		return 0;
+	}
+	fread(cmdline, 1, sizeof(cmdline) - 1, f);
+	fclose(f);
+
+	return strlen(cmdline) == len && strspn(cmdline, "x") == len;
+}
+
+int main(int argc, char **argv) {
+	char *ld_library_path = getenv("LD_LIBRARY_PATH");
+
+	if (on_stack(argv[0]) && on_stack(environ[0]) && on_stack((void *)getauxval(AT_EXECFN)) &&
+	    on_stack((void *)getauxval(AT_RANDOM)) && ld_library_path && on_stack(ld_library_path)) {
+		printf("Strings are on the stack\n");
+	}
+
+	if (rewrite_cmdline(argv[0])) {
+		printf("Rewrote /proc/self/cmdline\n");
+	}
+
+	// Depth in KiB
+	recurse(argc > 1 ? atoi(argv[1]) : 4096);
+	printf("Survived deep recursion\n");
+	return 0;
+}
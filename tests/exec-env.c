@@ -0,0 +1,16 @@
+#include <stdio.h>
+#include <unistd.h>
+
+// Runs a program with exactly the given environment, which unlike
+// with setenv() can contain duplicates
+int main(int argc, char **argv) {
+	if (argc < 2) {
+		fprintf(stderr, "Usage: %s <program> [NAME=value]...\n", argv[0]);
+		return 1;
+	}
+
+	char *child_argv[] = {argv[1], NULL};
+	execve(argv[1], child_argv, &argv[2]);
+	perror("execve");
+	return 1;
+}
@@ -33,6 +33,16 @@ int main(int argc, char **argv) {
 		return 1;
 	}
 
+	// Variables set for the program must survive the revert
+	char *extra = getenv("TEST_EXTRA");
+	if (extra) {
+		fprintf(stderr, "%s: TEST_EXTRA: %s\n", argv[0], extra);
+	}
+	char *other = getenv("TEST_OTHER");
+	if (other) {
+		fprintf(stderr, "%s: TEST_OTHER: %s\n", argv[0], other);
+	}
+
 	print_test();
 
 	// Our children must not be polluted by LD_LIBRARY_PATH
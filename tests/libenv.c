@@ -0,0 +1,17 @@
+#include <stdio.h>
+#include <string.h>
+
+int is_interesting_env(const char *env) {
+	return !strncmp(env, "TEST_", 5) || !strncmp(env, "LD_LIBRARY_PATH=", 16) ||
+	       !strncmp(env, "NIX_LD_LIBRARY_PATH=", 20);
+}
+
+// Constructors of libraries run before the entry point of the
+// program, so this sees the environment that ld.so was started with
+__attribute__((constructor)) static void print_ld_so_env(int argc, char **argv, char **envp) {
+	for (char **env = envp; *env; env++) {
+		if (is_interesting_env(*env)) {
+			printf("ld.so: %s\n", *env);
+		}
+	}
+}
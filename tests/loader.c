@@ -0,0 +1,79 @@
+// A "loader" without libc that only says hello
+#include <sys/syscall.h>
+
+static long sys3(long n, long a, long b, long c) {
+#if defined(__x86_64__)
+	long ret;
+	__asm__ volatile("syscall" : "=a"(ret) : "a"(n), "D"(a), "S"(b), "d"(c) : "rcx", "r11", "memory");
+	return ret;
+#elif defined(__arm__)
+	register long r7 __asm__("r7") = n;
+	register long r0 __asm__("r0") = a;
+	register long r1 __asm__("r1") = b;
+	register long r2 __asm__("r2") = c;
+	__asm__ volatile("svc #0" : "+r"(r0) : "r"(r7), "r"(r1), "r"(r2) : "memory");
+	return r0;
+#elif defined(__aarch64__)
+	register long x8 __asm__("x8") = n;
+	register long x0 __asm__("x0") = a;
+	register long x1 __asm__("x1") = b;
+	register long x2 __asm__("x2") = c;
+	__asm__ volatile("svc #0" : "+r"(x0) : "r"(x8), "r"(x1), "r"(x2) : "memory");
+	return x0;
+#elif defined(__riscv)
+	register long a7 __asm__("a7") = n;
+	register long a0 __asm__("a0") = a;
+	register long a1 __asm__("a1") = b;
+	register long a2 __asm__("a2") = c;
+	__asm__ volatile("ecall" : "+r"(a0) : "r"(a7), "r"(a1), "r"(a2) : "memory");
+	return a0;
+#elif defined(__s390x__)
+	register long r1 __asm__("r1") = n;
+	register long r2 __asm__("r2") = a;
+	register long r3 __asm__("r3") = b;
+	register long r4 __asm__("r4") = c;
+	__asm__ volatile("svc 0" : "+r"(r2) : "r"(r1), "r"(r3), "r"(r4) : "memory");
+	return r2;
+#elif defined(__loongarch__)
+	register long a7 __asm__("a7") = n;
+	register long a0 __asm__("a0") = a;
+	register long a1 __asm__("a1") = b;
+	register long a2 __asm__("a2") = c;
+	__asm__ volatile("syscall 0" : "+r"(a0) : "r"(a7), "r"(a1), "r"(a2)
+			 : "memory", "$t0", "$t1", "$t2", "$t3", "$t4", "$t5", "$t6", "$t7", "$t8");
+	return a0;
+#elif defined(__powerpc64__)
+	register long r0 __asm__("r0") = n;
+	register long r3 __asm__("r3") = a;
+	register long r4 __asm__("r4") = b;
+	register long r5 __asm__("r5") = c;
+	__asm__ volatile("sc" : "+r"(r0), "+r"(r3), "+r"(r4), "+r"(r5)
+			 : : "memory", "cr0", "ctr", "xer", "r6", "r7", "r8", "r9", "r10", "r11", "r12");
+	return r3;
+#else
+#error "Unsupported architecture"
+#endif
+}
+
+#define PRINT(msg) sys3(SYS_write, 1, (long)msg, sizeof(msg) - 1)
+
+extern const char __ehdr_start[];
+
+void _start(void) {
+	PRINT("Hello from the test loader\n");
+
+#if defined(__aarch64__) && defined(__ARM_FEATURE_BTI_DEFAULT)
+	// Faults if BTI is enforced, since there is no landing pad
+	PRINT("Branching without a landing pad\n");
+	__asm__ volatile("adr x16, 1f\n\tbr x16\n1:\n\tnop" : : : "x16");
+#endif
+
+#ifdef ALIGN
+	if (((unsigned long)__ehdr_start & (ALIGN - 1)) == 0) {
+		PRINT("Loader is aligned\n");
+	}
+#endif
+
+	sys3(SYS_exit_group, 0, 0, 0);
+	for (;;) {}
+}
@@ -0,0 +1,16 @@
+#include <stdint.h>
+
+void print_test();
+
+// Declare OUT_DIR as the library path of the program
+__attribute__((section(".note.nix-ld"), aligned(4), used))
+static const struct {
+	uint32_t namesz, descsz, type;
+	char name[8];
+	char desc[(sizeof(OUT_DIR) + 3) & ~3];
+} nix_ld_note = { 7, sizeof(OUT_DIR), 1, "nix-ld", OUT_DIR };
+
+int main() {
+	print_test();
+	return 0;
+}
@@ -0,0 +1,14 @@
+#include <stdio.h>
+
+extern char **environ;
+
+int is_interesting_env(const char *env);
+
+int main() {
+	for (char **env = environ; *env; env++) {
+		if (is_interesting_env(*env)) {
+			printf("program: %s\n", *env);
+		}
+	}
+	return 0;
+}
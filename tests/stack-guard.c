@@ -0,0 +1,41 @@
+#include <inttypes.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+// Prints the canary of nix-ld, which stays mapped after the handoff
+int main(int argc, char **argv) {
+	if (argc != 3) {
+		fprintf(stderr, "Usage: %s <nix-ld> <offset of __stack_chk_guard>\n", argv[0]);
+		return 1;
+	}
+
+	uintptr_t offset = strtoull(argv[2], NULL, 16);
+
+	FILE *maps = fopen("/proc/self/maps", "r");
+	if (!maps) {
+		perror("fopen");
+		return 1;
+	}
+
+	char line[4096];
+	while (fgets(line, sizeof(line), maps)) {
+		uintptr_t start;
+		unsigned long long file_offset;
+		char path[4096];
+
+		if (sscanf(line, "%" SCNxPTR "-%*" SCNxPTR " %*s %llx %*s %*s %4095s", &start,
+			   &file_offset, path) != 3) {
+			continue;
+		}
+
+		if (file_offset == 0 && strcmp(path, argv[1]) == 0) {
+			uintptr_t guard = *(volatile uintptr_t *)(start + offset);
+			printf("Stack guard: %" PRIxPTR "\n", guard);
+			return 0;
+		}
+	}
+
+	fprintf(stderr, "%s: %s is not mapped\n", argv[0], argv[1]);
+	return 1;
+}
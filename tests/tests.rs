@@ -1,11 +1,15 @@
 use std::env;
+use std::fs;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, ExitStatus};
 
 use rstest::*;
 use std::sync::OnceLock;
 use tempfile::TempDir;
 
+#[path = "../src/sha256.rs"]
+mod sha256;
+
 #[fixture]
 #[once]
 fn libtest() -> &'static str {
@@ -22,6 +26,27 @@ fn dt_needed_bin() -> PathBuf {
     compile_test_bin("dt-needed", &["test"])
 }
 
+/// A build of dt-needed with a known build-id, executed through a symlink.
+#[fixture]
+#[once]
+fn build_id_bin(_libtest: &str) -> PathBuf {
+    let bin = compile_test_bin_with_args(
+        "dt-needed",
+        "dt-needed-build-id",
+        &["test"],
+        &[&format!("-Wl,--build-id=0x{BUILD_ID}")],
+    );
+
+    let link_dir = get_tmpdir().path().join("link");
+    fs::create_dir_all(&link_dir).unwrap();
+    let link = link_dir.join("dt-needed-build-id");
+    std::os::unix::fs::symlink(&bin, &link).unwrap();
+    link
+}
+
+/// The GNU build-id of [`build_id_bin`].
+const BUILD_ID: &str = "5c8f3b1e0d2a4c6b8a9e7f1d3c5b7a9e1f2d4c6b";
+
 /// Check that we can run a simple binary.
 #[rstest]
 fn test_hello() {
@@ -81,13 +106,850 @@ fn test_dlopen(libtest: &str) {
     }
 }
 
+/// Check that a missing loader results in a helpful error.
+#[rstest]
+fn test_missing_loader() {
+    let bin = compile_test_bin("hello", &[]);
+
+    let (_, stderr) = Command::new(&bin)
+        .env("NIX_LD", "/nonexistent/ld.so")
+        .must_exit_with(127);
+    assert!(stderr.contains("from $NIX_LD: open \"/nonexistent/ld.so\""));
+    assert!(stderr.contains("No such file or directory (ENOENT)"));
+}
+
+/// Check that a NIX_LD that isn't a loader is reported as such.
+#[rstest]
+#[case::library("libtest.so", "so it is a shared library, not a loader")]
+#[case::program("hello", "its own interpreter")]
+#[case::script("wrapper.sh", "Is a script")]
+fn test_not_a_loader(libtest: &str, #[case] loader: &str, #[case] message: &str) {
+    let bin = compile_test_bin("hello", &[]);
+    fs::write(
+        Path::new(libtest).join("wrapper.sh"),
+        "#!/bin/sh\nexec /lib/ld.so \"$@\"\n",
+    )
+    .unwrap();
+
+    let (_, stderr) = Command::new(&bin)
+        .env("NIX_LD", Path::new(libtest).join(loader))
+        .must_exit_with(127);
+    assert!(stderr.contains(message));
+}
+
+/// Check that a non-relocatable loader is mapped at its address.
+#[rstest]
+fn test_fixed_loader() {
+    let bin = compile_test_bin("hello", &[]);
+    let loader = compile_test_loader("fixed-loader", &["-no-pie", "-fno-pic"]);
+
+    let (stdout, _) = Command::new(&bin).env("NIX_LD", &loader).must_succeed();
+    assert!(stdout.contains("Hello from the test loader"));
+}
+
+/// Check that the loader is aligned to its largest p_align.
+#[rstest]
+fn test_loader_alignment() {
+    let bin = compile_test_bin("hello", &[]);
+    let loader = compile_test_loader(
+        "aligned-loader",
+        &[
+            "-static-pie",
+            "-fPIE",
+            "-DALIGN=0x400000",
+            "-Wl,-z,max-page-size=0x400000",
+        ],
+    );
+
+    let (stdout, _) = Command::new(&bin).env("NIX_LD", &loader).must_succeed();
+    assert!(stdout.contains("Loader is aligned"));
+}
+
+/// Check that the GNU properties of the loader are read.
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+#[rstest]
+fn test_loader_gnu_property() {
+    let bin = compile_test_bin("hello", &[]);
+    let loader = compile_test_loader(
+        "cet-loader",
+        &["-static-pie", "-fPIE", "-fcf-protection=full"],
+    );
+
+    let (stdout, stderr) = Command::new(&bin)
+        .env("NIX_LD", &loader)
+        .env("NIX_LD_LOG", "debug")
+        .must_succeed();
+    assert!(stdout.contains("Hello from the test loader"));
+
+    // IBT and SHSTK
+    assert!(stderr.contains("GNU Features: 0x3"));
+}
+
+/// Check that BTI is enforced on the loader where the CPU supports it.
+#[cfg(target_arch = "aarch64")]
+#[rstest]
+fn test_loader_bti() {
+    use std::os::unix::process::ExitStatusExt;
+
+    let bin = compile_test_bin("hello", &[]);
+    let loader = compile_test_loader(
+        "bti-loader",
+        &[
+            "-static-pie",
+            "-fPIE",
+            "-mbranch-protection=bti",
+            "-Wl,-z,force-bti",
+        ],
+    );
+
+    let cpuinfo = fs::read_to_string("/proc/cpuinfo").unwrap();
+    let has_bti = cpuinfo
+        .lines()
+        .filter(|line| line.starts_with("Features"))
+        .any(|line| line.split_whitespace().any(|f| f == "bti"));
+
+    let (status, stdout, _) = Command::new(&bin)
+        .env("NIX_LD", &loader)
+        .env("NIX_LD_LOG", "debug")
+        .output_logged();
+    assert!(stdout.contains("Branching without a landing pad"));
+
+    if has_bti {
+        // SIGILL from the BTI exception
+        assert_eq!(status.signal(), Some(4));
+    } else {
+        assert!(status.success());
+        assert!(stdout.contains("Hello from the test loader"));
+    }
+}
+
+/// Check that a loader on a noexec file system is read into memory.
+#[ignore = "requires root"]
+#[rstest]
+fn test_noexec_loader() {
+    require_root();
+
+    let bin = compile_test_bin("hello", &[]);
+    let loader = compile_test_loader("noexec-loader", &["-static-pie", "-fPIE"]);
+    let mount_dir = get_tmpdir().path().join("noexec");
+    fs::create_dir_all(&mount_dir).unwrap();
+
+    let (stdout, stderr) = Command::new("unshare")
+        .args(["--mount", "sh", "-c"])
+        .arg("mount -t tmpfs -o noexec none \"$1\" && cp \"$2\" \"$1/ld.so\" && exec \"$3\"")
+        .arg("sh")
+        .arg(&mount_dir)
+        .arg(&loader)
+        .arg(&bin)
+        .env("NIX_LD", mount_dir.join("ld.so"))
+        .env("NIX_LD_LOG", "info")
+        .must_succeed();
+    assert!(stdout.contains("Hello from the test loader"));
+    assert!(stderr.contains("reading it into memory instead"));
+    assert!(stderr.contains("Operation not permitted (EPERM)"));
+}
+
+/// Check that nix-ld is linked with DT_RELR relocations.
+#[cfg(feature = "pack_relative_relocs")]
+#[rstest]
+fn test_pack_relative_relocs() {
+    let output = Command::new("readelf")
+        .arg("--dynamic")
+        .arg(EXE)
+        .output()
+        .expect("Failed to spawn readelf");
+    let dynamic = String::from_utf8(output.stdout).expect("readelf output contains non-UTF-8");
+    eprint!("{dynamic}");
+
+    assert!(output.status.success());
+    assert!(dynamic.contains("(RELR)"));
+}
+
+/// Check that NIX_LD_LIBRARY_PATH can be set in the config file.
+#[rstest]
+fn test_config_file(libtest: &str, dt_needed_bin: &Path) {
+    let config_home = get_tmpdir().path().join("config");
+    fs::create_dir_all(config_home.join("nix-ld")).unwrap();
+    fs::write(
+        config_home.join("nix-ld/config"),
+        format!("# Comment\nNIX_LD_LIBRARY_PATH = {libtest}\n"),
+    )
+    .unwrap();
+
+    let (stdout, _) = Command::new(dt_needed_bin)
+        .env_remove("LD_LIBRARY_PATH")
+        .env_remove("NIX_LD_LIBRARY_PATH")
+        .env("XDG_CONFIG_HOME", &config_home)
+        .must_succeed();
+    assert!(stdout.contains("Hello from libtest"));
+
+    // The environment takes precedence
+    let (_, stderr) = Command::new(dt_needed_bin)
+        .env_remove("LD_LIBRARY_PATH")
+        .env("NIX_LD_LIBRARY_PATH", "/nonexistent")
+        .env("XDG_CONFIG_HOME", &config_home)
+        .must_fail();
+    assert!(stderr.contains("loading shared"));
+}
+
+/// Check that profiles apply to matching programs only.
+#[rstest]
+fn test_config_profile(libtest: &str, dt_needed_bin: &Path) {
+    let config_home = get_tmpdir().path().join("config-profile");
+    fs::create_dir_all(config_home.join("nix-ld")).unwrap();
+    fs::write(
+        config_home.join("nix-ld/config"),
+        format!(
+            "[other]\n\
+             match-name = hello\n\
+             NIX_LD_LIBRARY_PATH = /nonexistent\n\
+             \n\
+             [dt-needed]\n\
+             match-path = */dt-*\n\
+             NIX_LD_LIBRARY_PATH = {libtest}\n\
+             env.LD_DEBUG = libs\n"
+        ),
+    )
+    .unwrap();
+
+    // The profile takes precedence over the environment
+    let (stdout, stderr) = Command::new(dt_needed_bin)
+        .env_remove("LD_LIBRARY_PATH")
+        .env("NIX_LD_LIBRARY_PATH", "/nonexistent")
+        .env("XDG_CONFIG_HOME", &config_home)
+        .must_succeed();
+    assert!(stdout.contains("Hello from libtest"));
+    assert!(stderr.contains("find library=libtest.so"));
+}
+
+/// Check that match-path sees the real path and match-build-id the build-id.
+#[rstest]
+#[case::real_path("match-path = {tmp}/dt-needed-build-id", true)]
+#[case::symlink_path("match-path = {link}/*", false)]
+#[case::build_id("match-build-id = {build_id}", true)]
+#[case::other_build_id("match-build-id = 0123456789abcdef", false)]
+fn test_config_profile_match(
+    libtest: &str,
+    build_id_bin: &Path,
+    #[case] key: &str,
+    #[case] matches: bool,
+) {
+    let tmp = fs::canonicalize(get_tmpdir().path()).unwrap();
+    let key = key
+        .replace("{tmp}", tmp.to_str().unwrap())
+        .replace("{link}", build_id_bin.parent().unwrap().to_str().unwrap())
+        .replace("{build_id}", BUILD_ID);
+
+    let config_home = TempDir::new_in(&tmp).unwrap();
+    fs::create_dir_all(config_home.path().join("nix-ld")).unwrap();
+    fs::write(
+        config_home.path().join("nix-ld/config"),
+        format!("[test]\n{key}\nNIX_LD_LIBRARY_PATH = {libtest}\n"),
+    )
+    .unwrap();
+
+    let mut command = Command::new(build_id_bin);
+    command
+        .env_remove("LD_LIBRARY_PATH")
+        .env("NIX_LD_LIBRARY_PATH", "/nonexistent")
+        .env("XDG_CONFIG_HOME", config_home.path());
+
+    if matches {
+        let (stdout, _) = command.must_succeed();
+        assert!(stdout.contains("Hello from libtest"));
+    } else {
+        let (_, stderr) = command.must_fail();
+        assert!(stderr.contains("loading shared"));
+    }
+}
+
+/// Check that sidecar files next to the program are honored.
+#[rstest]
+fn test_sidecar(libtest: &str, dt_needed_bin: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+
+    let app_dir = get_tmpdir().path().join("sidecar");
+    let bin = app_dir.join("bin/dt-needed");
+    fs::create_dir_all(app_dir.join("bin")).unwrap();
+    fs::copy(dt_needed_bin, &bin).unwrap();
+
+    // Sidecar files in parent directories are honored
+    fs::write(
+        app_dir.join(".nix-ld"),
+        format!("NIX_LD_LIBRARY_PATH = {libtest}\n"),
+    )
+    .unwrap();
+
+    let (stdout, _) = Command::new(&bin)
+        .env_remove("LD_LIBRARY_PATH")
+        .env("NIX_LD_LIBRARY_PATH", "/nonexistent")
+        .must_succeed();
+    assert!(stdout.contains("Hello from libtest"));
+
+    // ... unless a directory on the way is writable by others
+    fs::set_permissions(app_dir.join("bin"), fs::Permissions::from_mode(0o777)).unwrap();
+
+    let (_, stderr) = Command::new(&bin)
+        .env_remove("LD_LIBRARY_PATH")
+        .env("NIX_LD_LIBRARY_PATH", "/nonexistent")
+        .must_fail();
+    assert!(stderr.contains("loading shared"));
+
+    // ... without the sticky bit
+    fs::set_permissions(app_dir.join("bin"), fs::Permissions::from_mode(0o1777)).unwrap();
+
+    let (stdout, _) = Command::new(&bin)
+        .env_remove("LD_LIBRARY_PATH")
+        .env("NIX_LD_LIBRARY_PATH", "/nonexistent")
+        .must_succeed();
+    assert!(stdout.contains("Hello from libtest"));
+
+    fs::set_permissions(app_dir.join("bin"), fs::Permissions::from_mode(0o755)).unwrap();
+
+    // The directory-level sidecar file next to the program takes precedence
+    fs::write(
+        app_dir.join("bin/.nix-ld"),
+        "NIX_LD_LIBRARY_PATH = /nonexistent\n",
+    )
+    .unwrap();
+
+    let (_, stderr) = Command::new(&bin)
+        .env_remove("LD_LIBRARY_PATH")
+        .env("NIX_LD_LIBRARY_PATH", libtest)
+        .must_fail();
+    assert!(stderr.contains("loading shared"));
+
+    fs::write(
+        app_dir.join("bin/.nix-ld"),
+        format!("NIX_LD_LIBRARY_PATH = {libtest}\n"),
+    )
+    .unwrap();
+
+    let (stdout, _) = Command::new(&bin)
+        .env_remove("LD_LIBRARY_PATH")
+        .env("NIX_LD_LIBRARY_PATH", "/nonexistent")
+        .must_succeed();
+    assert!(stdout.contains("Hello from libtest"));
+
+    // The sidecar file of the program itself takes precedence
+    let sidecar = app_dir.join("bin/dt-needed.nix-ld");
+    fs::write(&sidecar, "NIX_LD_LIBRARY_PATH = /nonexistent\n").unwrap();
+
+    let (_, stderr) = Command::new(&bin)
+        .env_remove("LD_LIBRARY_PATH")
+        .env("NIX_LD_LIBRARY_PATH", libtest)
+        .must_fail();
+    assert!(stderr.contains("loading shared"));
+
+    // The loader cannot be set without a trusted-loader policy
+    fs::write(&sidecar, "NIX_LD = /nonexistent\n").unwrap();
+
+    let (stdout, stderr) = Command::new(&bin)
+        .env_remove("LD_LIBRARY_PATH")
+        .env("NIX_LD_LIBRARY_PATH", libtest)
+        .must_succeed();
+    assert!(stdout.contains("Hello from libtest"));
+    assert!(
+        stderr.contains("Ignoring NIX_LD from the sidecar file without a trusted-loader policy")
+    );
+
+    // Sidecar files writable by others are ignored
+    fs::write(&sidecar, "NIX_LD_LIBRARY_PATH = /nonexistent\n").unwrap();
+    fs::set_permissions(&sidecar, fs::Permissions::from_mode(0o664)).unwrap();
+
+    let (stdout, stderr) = Command::new(&bin)
+        .env_remove("LD_LIBRARY_PATH")
+        .env("NIX_LD_LIBRARY_PATH", libtest)
+        .must_succeed();
+    assert!(stdout.contains("Hello from libtest"));
+    assert!(stderr.contains("writable by group or others (mode 100664)"));
+
+    fs::set_permissions(&sidecar, fs::Permissions::from_mode(0o644)).unwrap();
+}
+
+/// Check that sidecar files owned by other users are ignored.
+#[rstest]
+#[ignore = "requires root"]
+fn test_sidecar_other_owner(libtest: &str, dt_needed_bin: &Path) {
+    require_root();
+
+    let bin = get_tmpdir().path().join("sidecar-owner/dt-needed");
+    fs::create_dir_all(bin.parent().unwrap()).unwrap();
+    fs::copy(dt_needed_bin, &bin).unwrap();
+
+    let sidecar = bin.with_extension("nix-ld");
+    fs::write(&sidecar, "NIX_LD_LIBRARY_PATH = /nonexistent\n").unwrap();
+    std::os::unix::fs::chown(&sidecar, Some(65534), Some(65534)).unwrap();
+
+    let (stdout, stderr) = Command::new(&bin)
+        .env_remove("LD_LIBRARY_PATH")
+        .env("NIX_LD_LIBRARY_PATH", libtest)
+        .must_succeed();
+    assert!(stdout.contains("Hello from libtest"));
+    assert!(stderr.contains("owned by uid 65534 instead of root or 0"));
+}
+
+/// Check that library paths declared in an ELF note are used.
+#[rstest]
+fn test_note_library_path(_libtest: &str) {
+    let bin = compile_test_bin("note", &["test"]);
+
+    let (stdout, _) = Command::new(&bin)
+        .env_remove("LD_LIBRARY_PATH")
+        .env("NIX_LD_LIBRARY_PATH", "/nonexistent")
+        .must_succeed();
+    assert!(stdout.contains("Hello from libtest"));
+
+    // Also merged with an existing LD_LIBRARY_PATH
+    let (stdout, _) = Command::new(&bin)
+        .env("LD_LIBRARY_PATH", "/nonexistent")
+        .env_remove("NIX_LD_LIBRARY_PATH")
+        .must_succeed();
+    assert!(stdout.contains("Hello from libtest"));
+}
+
+/// Check that the library path is sanitized.
+#[rstest]
+fn test_sanitize_library_path(libtest: &str, dt_needed_bin: &Path) {
+    let (stdout, stderr) = Command::new(dt_needed_bin)
+        .env("LD_LIBRARY_PATH", format!("::{libtest}:"))
+        .env("NIX_LD_LIBRARY_PATH", format!("{libtest}:/nonexistent"))
+        .env("NIX_LD_LOG", "debug")
+        .must_succeed();
+    assert!(stdout.contains("Hello from libtest"));
+    assert!(stderr.contains("Dropping empty library path component"));
+    assert!(stderr.contains(&format!("Dropping duplicate library path {libtest:?}")));
+    assert!(!stderr.contains("Dropping missing library path"));
+
+    let config_home = get_tmpdir().path().join("config-sanitize");
+    fs::create_dir_all(config_home.join("nix-ld")).unwrap();
+    fs::write(
+        config_home.join("nix-ld/config"),
+        "skip-missing-dirs = true\n",
+    )
+    .unwrap();
+
+    let (stdout, stderr) = Command::new(dt_needed_bin)
+        .env_remove("LD_LIBRARY_PATH")
+        .env("NIX_LD_LIBRARY_PATH", format!("/nonexistent:{libtest}"))
+        .env("NIX_LD_LOG", "debug")
+        .env("XDG_CONFIG_HOME", &config_home)
+        .must_succeed();
+    assert!(stdout.contains("Hello from libtest"));
+    assert!(stderr.contains("Dropping missing library path \"/nonexistent\""));
+
+    // Only the first 256 directories are kept
+    let many: Vec<_> = (0..300).map(|i| format!("/nonexistent/{i}")).collect();
+    let (stdout, stderr) = Command::new(dt_needed_bin)
+        .env_remove("LD_LIBRARY_PATH")
+        .env(
+            "NIX_LD_LIBRARY_PATH",
+            format!("{libtest}:{}", many.join(":")),
+        )
+        .must_succeed();
+    assert!(stdout.contains("Hello from libtest"));
+    assert!(stderr.contains("More than 256 library paths - Dropping the last 45"));
+}
+
+/// Check that extended attributes of the program are honored.
+#[rstest]
+fn test_xattr(libtest: &str, dt_needed_bin: &Path) {
+    let bin = get_tmpdir().path().join("xattr/dt-needed");
+    fs::create_dir_all(bin.parent().unwrap()).unwrap();
+    fs::copy(dt_needed_bin, &bin).unwrap();
+
+    let set = |name: &str, value: &str| match rustix::fs::setxattr(
+        &bin,
+        name,
+        value.as_bytes(),
+        rustix::fs::XattrFlags::empty(),
+    ) {
+        Ok(()) => true,
+        Err(rustix::io::Errno::NOTSUP) => false,
+        Err(e) => panic!("Failed to set {name}: {e}"),
+    };
+
+    if !set("user.nix-ld.library-path", libtest) {
+        eprintln!("Extended attributes are not supported - Skipping");
+        return;
+    }
+    set("user.nix-ld.env", "LD_DEBUG=libs\nFOO=bar\n");
+
+    let (stdout, stderr) = Command::new(&bin)
+        .env_remove("LD_LIBRARY_PATH")
+        .env("NIX_LD_LIBRARY_PATH", "/nonexistent")
+        .must_succeed();
+    assert!(stdout.contains("Hello from libtest"));
+    assert!(stderr.contains("find library=libtest.so"));
+
+    // The loader cannot be set without a trusted-loader policy
+    set("user.nix-ld.loader", "/nonexistent");
+
+    let (stdout, stderr) = Command::new(&bin)
+        .env_remove("LD_LIBRARY_PATH")
+        .env("NIX_LD_LIBRARY_PATH", "/nonexistent")
+        .must_succeed();
+    assert!(stdout.contains("Hello from libtest"));
+    assert!(
+        stderr.contains("Ignoring NIX_LD from extended attributes without a trusted-loader policy")
+    );
+}
+
+/// Check that the trusted-loader policy is enforced.
+#[ignore = "requires root"]
+#[rstest]
+#[case::trusted(true, 0, 0o755, None)]
+#[case::untrusted_prefix(false, 0, 0o755, Some("Not under a trusted prefix"))]
+#[case::not_owned_by_root(true, 65534, 0o755, Some("Owned by uid 65534 instead of root"))]
+#[case::group_writable(true, 0, 0o775, Some("Writable by group or others (mode 100775)"))]
+#[case::world_writable(true, 0, 0o757, Some("Writable by group or others (mode 100757)"))]
+fn test_trusted_loader(
+    #[case] under_prefix: bool,
+    #[case] uid: u32,
+    #[case] mode: u32,
+    #[case] message: Option<&str>,
+) {
+    use std::os::unix::fs::{PermissionsExt, chown};
+
+    require_root();
+
+    let name = format!("trust-{under_prefix}-{uid}-{mode:o}");
+    let bin = compile_test_bin("hello", &[]);
+    let system_loader = env::var_os("NIX_LD").expect("NIX_LD must point to the system ld.so");
+
+    let trust_dir = fs::canonicalize(get_tmpdir().path()).unwrap().join("trust");
+    let trusted_dir = trust_dir.join("trusted");
+    let loader_dir = if under_prefix {
+        trusted_dir.clone()
+    } else {
+        trust_dir.join("untrusted")
+    };
+    fs::create_dir_all(&loader_dir).unwrap();
+
+    let loader = loader_dir.join(format!("{name}.so"));
+    fs::copy(system_loader, &loader).unwrap();
+    chown(&loader, Some(uid), None).unwrap();
+    fs::set_permissions(&loader, fs::Permissions::from_mode(mode)).unwrap();
+
+    let config = format!("trusted-loader-prefix = {}\n", trusted_dir.display());
+    let mut command = with_system_config(&name, &config, &bin);
+    command.env("NIX_LD", &loader);
+
+    if let Some(message) = message {
+        let (_, stderr) = command.must_exit_with(127);
+        assert!(stderr.contains(&format!(
+            "from $NIX_LD: trust {:?}: {message}",
+            loader.display()
+        )));
+    } else {
+        let (stdout, _) = command.must_succeed();
+        assert!(stdout.contains("Hello, world!"));
+    }
+}
+
+/// Check that the allowlist is enforced.
+#[ignore = "requires root"]
+#[rstest]
+#[case::path("path", None)]
+#[case::sha256("sha256", None)]
+#[case::denied("denied", Some("is not in the allowlist"))]
+#[case::direct("direct", Some("nix-ld was executed directly"))]
+fn test_allowlist(#[case] kind: &str, #[case] message: Option<&str>) {
+    require_root();
+
+    let hello_bin = compile_test_bin("hello", &[]);
+    let allowlist_dir = fs::canonicalize(get_tmpdir().path())
+        .unwrap()
+        .join("allowlist");
+    fs::create_dir_all(&allowlist_dir).unwrap();
+
+    let bin = allowlist_dir.join(format!("hello-{kind}"));
+    fs::copy(hello_bin, &bin).unwrap();
+
+    let line = match kind {
+        "path" => format!("path {}/hello-p*", allowlist_dir.display()),
+        "sha256" => format!("sha256 {}", sha256_hex([&fs::read(&bin).unwrap()[..]])),
+        _ => format!("path {}/hello-p*", allowlist_dir.display()),
+    };
+
+    let allowlist = allowlist_dir.join(format!("{kind}.allowlist"));
+    fs::write(&allowlist, format!("# Test allowlist\n{line}\n")).unwrap();
+
+    let config = format!("allowlist = {}\n", allowlist.display());
+    let mut command = if kind == "direct" {
+        let mut command = with_system_config(&format!("allowlist-{kind}"), &config, Path::new(EXE));
+        command.arg(&bin);
+        command
+    } else {
+        with_system_config(&format!("allowlist-{kind}"), &config, &bin)
+    };
+    command.env("NIX_LD_LOG", "info");
+
+    if let Some(message) = message {
+        let (_, stderr) = command.must_exit_with(126);
+        assert!(stderr.contains("Refusing to run the program: "));
+        assert!(stderr.contains(message));
+    } else {
+        let (stdout, stderr) = command.must_succeed();
+        assert!(stdout.contains("Hello, world!"));
+        assert!(stderr.contains(&format!("{:?} is allowed by {line:?}", bin.display())));
+    }
+}
+
+/// Check that an unreadable system-wide config file is fatal.
+#[rstest]
+#[case::unreadable("unreadable", "Permission denied (EACCES)")]
+#[case::too_large("too-large", "larger than 65536 bytes")]
+#[ignore = "requires root"]
+fn test_system_config_error(#[case] kind: &str, #[case] message: &str) {
+    use std::os::unix::fs::PermissionsExt;
+
+    require_root();
+
+    let bin = compile_test_bin("hello", &[]);
+    let name = format!("config-{kind}");
+
+    let (config, mode) = match kind {
+        "unreadable" => ("NIX_LD_LOG = info\n".to_string(), 0o000),
+        _ => (format!("# {}\n", "x".repeat(64 * 1024)), 0o644),
+    };
+
+    // Root can read any file, so drop that ability after mounting
+    let mut command = with_system_config(&name, &config, Path::new("setpriv"));
+    command
+        .args(["--bounding-set", "-dac_override,-dac_read_search"])
+        .args(["--inh-caps", "-dac_override,-dac_read_search"])
+        .arg(&bin);
+
+    let config_path = get_tmpdir()
+        .path()
+        .join(format!("etc-{name}/upper/nix-ld/config"));
+    fs::set_permissions(&config_path, fs::Permissions::from_mode(mode)).unwrap();
+
+    let (stdout, stderr) = command.must_exit_with(127);
+    assert!(!stdout.contains("Hello, world!"));
+    assert!(stderr.contains("Cannot read \"/etc/nix-ld/config\""));
+    assert!(stderr.contains(message));
+}
+
+/// Check SHA-256 against known answers.
+#[rstest]
+#[case::empty(
+    b"",
+    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+)]
+#[case::abc(
+    b"abc",
+    "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+)]
+#[case::two_blocks(
+    b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+    "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+)]
+fn test_sha256(#[case] input: &[u8], #[case] expected: &str) {
+    assert_eq!(sha256_hex([input]), expected);
+}
+
+/// Check SHA-256 of a large input fed in uneven chunks.
+#[rstest]
+fn test_sha256_million_a() {
+    let input = vec![b'a'; 1_000_000];
+    assert_eq!(
+        sha256_hex(input.chunks(997)),
+        "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+    );
+}
+
+/// Check that the stack protector canary is seeded from AT_RANDOM.
+#[rstest]
+fn test_stack_guard() {
+    let bin = compile_test_bin("stack-guard", &[]);
+    let exe = fs::canonicalize(EXE).unwrap();
+
+    let output = Command::new("nm")
+        .arg(&exe)
+        .output()
+        .expect("Failed to spawn nm");
+    let symbols = String::from_utf8(output.stdout).expect("nm output contains non-UTF-8");
+    let offset = symbols
+        .lines()
+        .find(|line| line.ends_with(" __stack_chk_guard"))
+        .and_then(|line| line.split_whitespace().next())
+        .expect("__stack_chk_guard not found");
+
+    let read_guard = || {
+        let (stdout, _) = Command::new(&bin).arg(&exe).arg(offset).must_succeed();
+        let guard = stdout
+            .lines()
+            .find_map(|line| line.strip_prefix("Stack guard: "))
+            .expect("Stack guard not printed");
+        usize::from_str_radix(guard, 16).unwrap()
+    };
+
+    let guard = read_guard();
+    assert_ne!(guard, 0x1234567890abcdef_u64 as usize);
+    assert_eq!(guard.to_ne_bytes()[0], 0);
+
+    // Each run gets its own AT_RANDOM
+    assert_ne!(guard, read_guard());
+}
+
+/// Check that NIX_LD is ignored for setuid programs.
+#[ignore = "requires root"]
+#[rstest]
+fn test_secure_execution() {
+    use std::os::unix::fs::{PermissionsExt, chown};
+
+    require_root();
+
+    let hello_bin = compile_test_bin("hello", &[]);
+    let bin = get_tmpdir().path().join("setuid-hello");
+    fs::copy(hello_bin, &bin).unwrap();
+
+    // Running a program owned by another user as root is enough
+    chown(&bin, Some(65534), None).unwrap();
+    fs::set_permissions(&bin, fs::Permissions::from_mode(0o4755)).unwrap();
+
+    let (stdout, stderr) = Command::new(&bin)
+        .env("NIX_LD", "/nonexistent")
+        .env("NIX_LD_LOG", "info")
+        .must_succeed();
+    assert!(stdout.contains("Hello, world!"));
+    assert_eq!(stderr.matches("in secure-execution mode").count(), 1);
+    assert!(stderr.contains("Ignoring NIX_LD* environment variables in secure-execution mode"));
+}
+
+/// Check that the program can use the stack with rebuilt vectors like the original one.
+#[rstest]
+#[case::default_limit("4096")]
+#[case::unlimited("65536")]
+fn test_new_stack(#[case] depth_kib: &str) {
+    let bin = compile_test_bin("deep-stack", &[]);
+
+    // Recursing 64 MiB deep needs more than the usual limit
+    let ulimit = if depth_kib == "65536" {
+        "ulimit -s unlimited && "
+    } else {
+        ""
+    };
+
+    // Adding LD_LIBRARY_PATH requires new vectors
+    let (stdout, stderr) = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{ulimit}exec \"$0\" \"$1\""))
+        .arg(&bin)
+        .arg(depth_kib)
+        .env_remove("LD_LIBRARY_PATH")
+        .env_remove("NIX_LD_LIBRARY_PATH")
+        .env("NIX_LD_LOG", "info")
+        .must_succeed();
+    assert!(stderr.contains("Building new vectors"));
+    assert!(stdout.contains("Strings are on the stack"));
+    assert!(stdout.contains("Rewrote /proc/self/cmdline"));
+    assert!(stdout.contains("Survived deep recursion"));
+}
+
+/// Check what ld.so and the program see after environment edits.
+#[cfg(all(
+    feature = "entry_trampoline",
+    any(
+        target_arch = "x86_64",
+        target_arch = "x86",
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "riscv64",
+        target_arch = "s390x",
+        target_arch = "loongarch64",
+        target_arch = "powerpc64"
+    )
+))]
+#[rstest]
+fn test_env_edits(libtest: &str) {
+    compile_test_lib("env");
+    let bin = compile_test_bin("print-env", &["env"]);
+    let launcher = compile_test_bin("exec-env", &[]);
+
+    let config_home = get_tmpdir().path().join("config-env-edits");
+    fs::create_dir_all(config_home.join("nix-ld")).unwrap();
+    fs::write(
+        config_home.join("nix-ld/config"),
+        format!(
+            "[print-env]\n\
+             match-name = print-env\n\
+             NIX_LD_LIBRARY_PATH = {libtest}\n\
+             env.TEST_A = a\n\
+             env.TEST_B = b\n\
+             env.TEST_DUP = profile\n"
+        ),
+    )
+    .unwrap();
+
+    // Runs the program with exactly this environment
+    let run = |env: &[&str]| -> Vec<String> {
+        let mut command = Command::new(&launcher);
+        command.arg(&bin);
+        if let Ok(nix_ld) = env::var("NIX_LD") {
+            command.arg(format!("NIX_LD={nix_ld}"));
+        }
+        command.arg(format!("XDG_CONFIG_HOME={}", config_home.display()));
+        command.args(env);
+
+        let (stdout, _) = command.must_succeed();
+        stdout
+            .lines()
+            .filter(|line| line.starts_with("ld.so: ") || line.starts_with("program: "))
+            .map(String::from)
+            .collect()
+    };
+
+    // Added variables are in order, and only LD_LIBRARY_PATH is reverted
+    assert_eq!(
+        run(&[]),
+        [
+            format!("ld.so: LD_LIBRARY_PATH={libtest}"),
+            "ld.so: TEST_A=a".to_string(),
+            "ld.so: TEST_B=b".to_string(),
+            "ld.so: TEST_DUP=profile".to_string(),
+            "program: LD_LIBRARY_PATH=".to_string(),
+            "program: TEST_A=a".to_string(),
+            "program: TEST_B=b".to_string(),
+            "program: TEST_DUP=profile".to_string(),
+        ]
+    );
+
+    // The earlier duplicate is removed for good, and the renamed
+    // NIX_LD_LIBRARY_PATH after it is still restored
+    assert_eq!(
+        run(&[
+            "TEST_DUP=first",
+            "NIX_LD_LIBRARY_PATH=/nonexistent",
+            "TEST_DUP=second",
+        ]),
+        [
+            format!("ld.so: LD_LIBRARY_PATH={libtest}"),
+            "ld.so: TEST_DUP=profile".to_string(),
+            "ld.so: TEST_A=a".to_string(),
+            "ld.so: TEST_B=b".to_string(),
+            "program: NIX_LD_LIBRARY_PATH=/nonexistent".to_string(),
+            "program: TEST_DUP=profile".to_string(),
+            "program: TEST_A=a".to_string(),
+            "program: TEST_B=b".to_string(),
+        ]
+    );
+}
+
 /// Check that LD_LIBRARY_PATH is restored.
 #[cfg(all(
     feature = "entry_trampoline",
     any(
         target_arch = "x86_64",
+        target_arch = "x86",
+        target_arch = "arm",
         target_arch = "aarch64",
-        target_arch = "riscv64"
+        target_arch = "riscv64",
+        target_arch = "s390x",
+        target_arch = "loongarch64",
+        target_arch = "powerpc64"
     )
 ))]
 #[rstest]
@@ -117,6 +979,32 @@ fn test_ld_path_restore(libtest: &str, _dt_needed_bin: &Path) {
         assert!(stderr.contains("Launching child process"));
         assert!(stderr.contains("loading shared")); // error from the child process
     }
+
+    // Now with variables added for the program, which moves the
+    // environment around
+    {
+        let config_home = get_tmpdir().path().join("config-ld-path-restore");
+        fs::create_dir_all(config_home.join("nix-ld")).unwrap();
+        fs::write(
+            config_home.join("nix-ld/config"),
+            "[ld-path-restore]\n\
+             match-name = ld-path-restore\n\
+             env.TEST_EXTRA = extra\n\
+             env.TEST_OTHER = other\n",
+        )
+        .unwrap();
+
+        let (stdout, stderr) = Command::new(&bin)
+            .env("LD_LIBRARY_PATH", "NEEDLE")
+            .env("NIX_LD_LIBRARY_PATH", &nix_ld_path)
+            .env("XDG_CONFIG_HOME", &config_home)
+            .must_succeed();
+        assert!(stderr.contains("LD_LIBRARY_PATH contains needle"));
+        assert!(stderr.contains("TEST_EXTRA: extra"));
+        assert!(stderr.contains("TEST_OTHER: other"));
+        assert!(stdout.contains("Hello from libtest"));
+        assert!(stderr.contains("loading shared"));
+    }
 }
 
 // Utilities
@@ -144,6 +1032,41 @@ fn get_source_file(file: &str) -> PathBuf {
     base.join(file)
 }
 
+/// Runs a program with a system-wide config file.
+///
+/// The config file is put into an overlay of `/etc` in a private mount
+/// namespace, which requires root.
+fn with_system_config(name: &str, config: &str, program: &Path) -> Command {
+    let base = get_tmpdir().path().join(format!("etc-{name}"));
+    let upper = base.join("upper");
+    let work = base.join("work");
+    fs::create_dir_all(upper.join("nix-ld")).unwrap();
+    fs::create_dir_all(&work).unwrap();
+    fs::write(upper.join("nix-ld/config"), config).unwrap();
+
+    let mut command = Command::new("unshare");
+    command
+        .args(["--mount", "sh", "-c"])
+        .arg(
+            "mount -t overlay overlay -o \"lowerdir=/etc,upperdir=$1,workdir=$2\" /etc \
+             && shift 2 && exec \"$@\"",
+        )
+        .arg("sh")
+        .arg(&upper)
+        .arg(&work)
+        .arg(program);
+    command
+}
+
+/// Returns the hex SHA-256 of data fed in chunks.
+fn sha256_hex<'a>(chunks: impl IntoIterator<Item = &'a [u8]>) -> String {
+    let mut hasher = sha256::Sha256::new();
+    for chunk in chunks {
+        hasher.update(chunk);
+    }
+    hasher.finish().iter().map(|b| format!("{b:02x}")).collect()
+}
+
 fn compile_test_lib(name: &str) {
     let cc = find_cc();
     let source_path = get_source_file(&format!("tests/lib{name}.c"));
@@ -162,9 +1085,14 @@ fn compile_test_lib(name: &str) {
 }
 
 fn compile_test_bin(name: &str, libs: &[&str]) -> PathBuf {
+    compile_test_bin_with_args(name, name, libs, &[])
+}
+
+/// Builds a test binary under another name with extra compiler arguments.
+fn compile_test_bin_with_args(name: &str, out_name: &str, libs: &[&str], args: &[&str]) -> PathBuf {
     let cc = find_cc();
     let source_path = get_source_file(&format!("tests/{name}.c"));
-    let out_path = get_tmpdir().path().join(name);
+    let out_path = get_tmpdir().path().join(out_name);
 
     let out_dir_arg = format!("-DOUT_DIR=\"{}\"", get_tmpdir().path().to_str().unwrap());
     let dynamic_linker_arg = format!("-Wl,--dynamic-linker,{EXE}");
@@ -177,23 +1105,56 @@ fn compile_test_bin(name: &str, libs: &[&str]) -> PathBuf {
         .arg("-L")
         .arg(get_tmpdir().path())
         .args(libs.iter().map(|l| format!("-l{l}")))
+        .args(args)
+        .arg(source_path)
+        .status()
+        .expect("Failed to spawn compiler");
+
+    assert!(status.success(), "Failed to build test binary {out_name}");
+
+    out_path
+}
+
+/// Builds the test loader without libc.
+fn compile_test_loader(name: &str, args: &[&str]) -> PathBuf {
+    let cc = find_cc();
+    let source_path = get_source_file("tests/loader.c");
+    let out_path = get_tmpdir().path().join(name);
+
+    let status = Command::new(cc)
+        .arg("-nostdlib")
+        .args(args)
+        .arg("-Wl,--no-dynamic-linker")
+        .arg("-Wl,--export-dynamic")
+        .arg("-o")
+        .arg(&out_path)
         .arg(source_path)
         .status()
         .expect("Failed to spawn compiler");
 
-    assert!(status.success(), "Failed to build test binary {name}");
+    assert!(status.success(), "Failed to build test loader {name}");
 
     out_path
 }
 
+/// Fails tests that need root, which are ignored by default.
+fn require_root() {
+    assert!(
+        rustix::process::geteuid().is_root(),
+        "This test must be run as root"
+    );
+}
+
 trait CommandExt {
+    fn output_logged(&mut self) -> (ExitStatus, String, String);
     fn output_checked(&mut self, want_success: bool) -> (String, String);
     fn must_succeed(&mut self) -> (String, String);
     fn must_fail(&mut self) -> (String, String);
+    fn must_exit_with(&mut self, code: i32) -> (String, String);
 }
 
 impl CommandExt for Command {
-    fn output_checked(&mut self, want_success: bool) -> (String, String) {
+    fn output_logged(&mut self) -> (ExitStatus, String, String) {
         eprintln!("Running binary {:?}", self.get_program());
         let output = self.output().expect("Failed to spawn test binary");
 
@@ -203,15 +1164,21 @@ impl CommandExt for Command {
         print!("{stdout}");
         eprint!("{stderr}");
 
+        (output.status, stdout, stderr)
+    }
+
+    fn output_checked(&mut self, want_success: bool) -> (String, String) {
+        let (status, stdout, stderr) = self.output_logged();
+
         if want_success {
             assert!(
-                output.status.success(),
+                status.success(),
                 "{:?} did not run successfully",
                 self.get_program()
             );
         } else {
             assert!(
-                !output.status.success(),
+                !status.success(),
                 "{:?} unexpectedly succeeded",
                 self.get_program()
             );
@@ -227,4 +1194,15 @@ impl CommandExt for Command {
     fn must_fail(&mut self) -> (String, String) {
         self.output_checked(false)
     }
+
+    fn must_exit_with(&mut self, code: i32) -> (String, String) {
+        let (status, stdout, stderr) = self.output_logged();
+        assert_eq!(
+            status.code(),
+            Some(code),
+            "{:?} exited with {status} instead of status {code}",
+            self.get_program()
+        );
+        (stdout, stderr)
+    }
 }